@@ -0,0 +1,36 @@
+use std::env;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// output of a git command, not set if git is not available
+fn git(args: &[&str]) -> Option<String> {
+    Command::new("git").args(args).output().ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|output| output.trim().to_owned())
+}
+
+/// value of an environment variable of the build, else of the git command
+fn git_info(name: &str, args: &[&str]) -> Option<String> {
+    println!("cargo:rerun-if-env-changed={name}");
+    env::var(name).ok().filter(|value| !value.is_empty()).or_else(|| git(args))
+}
+
+fn main() {
+    // the git values may be passed in where there is no git, e.g. in the docker build
+    let hash = git_info("GIT_HASH", &["rev-parse", "HEAD"]).unwrap_or_else(|| "unknown".to_owned());
+    let branch = git_info("GIT_BRANCH", &["rev-parse", "--abbrev-ref", "HEAD"]).unwrap_or_default();
+    let dirty = git_info("GIT_DIRTY", &["status", "--porcelain"])
+        .map(|status| (status != "false" && !status.is_empty()).to_string())
+        .unwrap_or_default();
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    println!("cargo:rustc-env=GIT_HASH={hash}");
+    println!("cargo:rustc-env=GIT_BRANCH={branch}");
+    println!("cargo:rustc-env=GIT_DIRTY={dirty}");
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={timestamp}");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    // the working tree is dirty as soon as a source changes
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-changed=src");
+}
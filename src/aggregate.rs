@@ -0,0 +1,62 @@
+//! Summarizing several data points into one
+
+use anyhow::{bail, Result};
+
+/// function used to summarize the values of one field over a window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Aggregation {
+    Min,
+    Max,
+    Mean,
+}
+
+impl Aggregation {
+    /// suffix of the summarized field
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Aggregation::Min => "min",
+            Aggregation::Max => "max",
+            Aggregation::Mean => "mean",
+        }
+    }
+
+    /// summarize the values; `None` if there are no values
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn apply(self, values: &[f64]) -> Option<f64> {
+        if values.is_empty() {
+            return None;
+        }
+        Some(match self {
+            Aggregation::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            Aggregation::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            Aggregation::Mean => values.iter().sum::<f64>() / values.len() as f64,
+        })
+    }
+}
+
+/// parse a comma separated list of aggregation functions, e.g. `min, max, mean`
+pub(crate) fn parse_aggregations(value: &str) -> Result<Vec<Aggregation>> {
+    value.split(',').map(str::trim).filter(|s| !s.is_empty()).map(|name| {
+        Ok(match name {
+            "min" => Aggregation::Min,
+            "max" => Aggregation::Max,
+            "mean" => Aggregation::Mean,
+            _ => bail!("Unknown aggregation {name}, possible values: min, max, mean"),
+        })
+    }).collect()
+}
+
+/// collect the values of every field over all samples, keeping the order of the fields
+pub(crate) fn collect_fields<I>(samples: I) -> Vec<(&'static str, Vec<f64>)>
+where
+    I: IntoIterator<Item = Vec<(&'static str, f64)>>,
+{
+    let mut fields: Vec<(&'static str, Vec<f64>)> = Vec::new();
+    for (name, value) in samples.into_iter().flatten() {
+        match fields.iter_mut().find(|(field, _)| *field == name) {
+            Some((_, values)) => values.push(value),
+            None => fields.push((name, vec![value])),
+        }
+    }
+    fields
+}
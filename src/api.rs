@@ -1,33 +1,144 @@
+use std::collections::HashMap;
+use std::env::consts::ARCH;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::stream::{self, BoxStream};
+use futures_util::StreamExt;
 use poem::Result;
 use poem::web::Data;
-use poem_openapi::{ApiResponse, Object, OpenApi, Tags};
-use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Enum, Object, OpenApi, Tags};
+use poem_openapi::param::Query;
+use poem_openapi::payload::{EventStream, Json, PlainText};
+use serde_json::json;
+use time::OffsetDateTime;
+use tokio::sync::RwLock;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::sleep;
+use tracing::error;
 
 use crate::AppState;
-use crate::inverter::SolarData;
-use crate::wattpilot::WattpilotData;
+use crate::history::{self, HistoryPoint};
+use crate::influx::point_line;
+use crate::inverter::{fetch_solar_values, SolarData};
+use crate::plausibility::Verdict;
+use crate::stats::{DailyStats, RollingAverages};
+use crate::utils::current_point;
+use crate::wattpilot::{ForceState, ModelStatus, Wattpilot, WattpilotData, WattpilotStatus};
 
 // GLOBALS -----------------------------------------------------------------------------------------
+/// how often to check for the confirmation of a new force state, every 100ms
+const FORCE_CONFIRM_TRIES: u32 = 30;
+/// interval of the keep alive comments of the stream
+const STREAM_KEEP_ALIVE: Duration = Duration::from_secs(15);
 
 // -------------------------------------------------------------------------------------------------
 
 // OBJECTS -----------------------------------------------------------------------------------------
+#[derive(Object)]
+struct WattpilotRespData {
+    /// state of the connection to the wattpilot; data are placeholders unless `Authenticated`
+    status: WattpilotStatus,
+    /// data of the wattpilot
+    data: WattpilotData,
+}
+
 #[derive(Object)]
 struct SolarRespData {
-    /// data of the connected wattpilot
+    /// state of the connection to the first wattpilot; data are placeholders unless `Authenticated`
+    wattpilot_status: WattpilotStatus,
+    /// data of the first wattpilot
     wattpilot_data: WattpilotData,
+    /// all wattpilots by id (host and port of their url)
+    wattpilots: HashMap<String, WattpilotRespData>,
     /// data of rest of system
     solar_data: SolarData,
 }
+#[derive(Object)]
+struct WattpilotConnectionData {
+    /// id of the wattpilot (host and port of its url)
+    id: String,
+    /// whether the wattpilot is authenticated
+    authenticated: bool,
+    /// timestamp of last received update
+    last_updated: OffsetDateTime,
+    /// how often the websocket was reconnected
+    reconnect_count: u64,
+    /// last error of the connection
+    last_error: Option<String>,
+}
+#[derive(Object)]
+struct VersionRespData {
+    /// version of the crate
+    version: String,
+    /// hash of the git commit this build is based on; `unknown` if git was not available
+    git_hash: String,
+    /// git branch of the build; not set if git was not available
+    git_branch: Option<String>,
+    /// whether the build had uncommitted changes; not set if git was not available
+    git_dirty: Option<bool>,
+    /// time of the build
+    build_time: OffsetDateTime,
+    /// cpu architecture, e.g. `x86_64` or `aarch64`
+    arch: String,
+}
+#[derive(Object)]
+struct HealthRespData {
+    /// sources whose data is too old, e.g. `solar` or `wattpilot <id>`; empty if everything is fine
+    stale: Vec<String>,
+    /// how far the clock of the inverter is behind the clock of this server; a warning is logged above
+    /// `inverter_clock_skew_warn_secs`; not set if the inverter does not report its time; data in seconds
+    #[oai(skip_serializing_if_is_none)]
+    inverter_clock_skew: Option<i64>,
+}
+#[derive(Object)]
+struct SetForceRespData {
+    /// whether the wattpilot reported the new force state in time
+    confirmed: bool,
+    /// force state reported by the wattpilot
+    force_state: ForceState,
+    /// model status reported by the wattpilot
+    model_status: ModelStatus,
+}
 // -------------------------------------------------------------------------------------------------
 
 // ERRORS ------------------------------------------------------------------------------------------
+/// why a wattpilot can not be controlled
+enum Unavailable {
+    /// no wattpilot with this id
+    Unknown(String),
+    /// no wattpilot configured
+    NotConfigured,
+    /// wattpilot is not authenticated
+    NotAuthenticated,
+}
+
+impl From<Unavailable> for SetCurrentResp {
+    fn from(value: Unavailable) -> Self {
+        match value {
+            Unavailable::Unknown(id) => SetCurrentResp::BadRequest(PlainText(format!("Unknown wattpilot {id}"))),
+            Unavailable::NotConfigured => SetCurrentResp::ServiceUnavailable(PlainText("Wattpilot is not configured".to_owned())),
+            Unavailable::NotAuthenticated => SetCurrentResp::ServiceUnavailable(PlainText("Wattpilot is not authenticated".to_owned())),
+        }
+    }
+}
+
+impl From<Unavailable> for SetForceResp {
+    fn from(value: Unavailable) -> Self {
+        match value {
+            Unavailable::Unknown(id) => SetForceResp::BadRequest(PlainText(format!("Unknown wattpilot {id}"))),
+            Unavailable::NotConfigured => SetForceResp::ServiceUnavailable(PlainText("Wattpilot is not configured".to_owned())),
+            Unavailable::NotAuthenticated => SetForceResp::ServiceUnavailable(PlainText("Wattpilot is not authenticated".to_owned())),
+        }
+    }
+}
 
 // -------------------------------------------------------------------------------------------------
 
 // RESPONSES ---------------------------------------------------------------------------------------
 
 #[derive(ApiResponse)]
+#[allow(clippy::large_enum_variant)]
 enum SolarResp {
     /// everything is fine
     #[oai(status = 200)]
@@ -38,9 +149,159 @@ enum SolarResp {
     #[allow(dead_code)]
     InternalServerError,
 }
+
+#[derive(ApiResponse)]
+#[allow(clippy::large_enum_variant)]
+enum RefreshResp {
+    /// freshly fetched values
+    #[oai(status = 200)]
+    Ok(Json<SolarData>),
+
+    /// values were dropped as implausible, the last values are kept
+    #[oai(status = 422)]
+    UnprocessableEntity(PlainText<String>),
+
+    /// values could not be fetched
+    #[oai(status = 500)]
+    InternalServerError,
+}
+
+#[derive(ApiResponse)]
+enum InverterRawResp {
+    /// raw response of the inverter
+    #[oai(status = 200)]
+    Ok(PlainText<String>),
+
+    /// no response with this index yet
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(ApiResponse)]
+enum WattpilotValuesResp {
+    /// state and data of the wattpilot
+    #[oai(status = 200)]
+    Ok(Json<WattpilotRespData>),
+
+    /// no wattpilot with this id
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+
+    /// wattpilot is not configured
+    #[oai(status = 503)]
+    ServiceUnavailable(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum HistoryResp {
+    /// values of the field, oldest first
+    #[oai(status = 200)]
+    Ok(Json<Vec<HistoryPoint>>),
+
+    /// field is unknown or time range is invalid
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+
+    /// influx could not be queried
+    #[oai(status = 500)]
+    InternalServerError,
+
+    /// influx is not configured
+    #[oai(status = 503)]
+    ServiceUnavailable(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum HealthResp {
+    /// all data is fresh
+    #[oai(status = 200)]
+    Ok(Json<HealthRespData>),
+
+    /// some data is too old
+    #[oai(status = 503)]
+    ServiceUnavailable(Json<HealthRespData>),
+}
+
+#[derive(ApiResponse)]
+enum WattpilotConnectionResp {
+    /// connection state of all wattpilots
+    #[oai(status = 200)]
+    Ok(Json<Vec<WattpilotConnectionData>>),
+}
+
+#[derive(ApiResponse)]
+enum SetForceResp {
+    /// force state was sent to the wattpilot
+    #[oai(status = 200)]
+    Ok(Json<SetForceRespData>),
+
+    /// wattpilot is unknown
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+
+    /// wattpilot is not configured or not authenticated
+    #[oai(status = 503)]
+    ServiceUnavailable(PlainText<String>),
+
+    /// sending to the wattpilot failed
+    #[oai(status = 500)]
+    InternalServerError,
+}
+
+#[derive(ApiResponse)]
+enum SetCurrentResp {
+    /// current was sent to the wattpilot
+    #[oai(status = 200)]
+    Ok,
+
+    /// current is out of range or wattpilot is unknown
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+
+    /// wattpilot is not configured or not authenticated
+    #[oai(status = 503)]
+    ServiceUnavailable(PlainText<String>),
+
+    /// sending to the wattpilot failed
+    #[oai(status = 500)]
+    InternalServerError,
+}
 // -------------------------------------------------------------------------------------------------
 
 // REQUESTS ----------------------------------------------------------------------------------------
+#[derive(Object)]
+struct SetCurrentReq {
+    /// charging current; data in ampere, 6 to 16
+    amp: u8,
+    /// id of the wattpilot (host and port of its url); not set = first wattpilot
+    id: Option<String>,
+}
+
+#[derive(Enum, Clone, Copy)]
+#[oai(rename_all = "lowercase")]
+enum ForceStateReq {
+    Off,
+    Neutral,
+    On,
+}
+
+impl From<ForceStateReq> for ForceState {
+    fn from(value: ForceStateReq) -> Self {
+        match value {
+            ForceStateReq::Off => ForceState::Off,
+            ForceStateReq::Neutral => ForceState::Neutral,
+            ForceStateReq::On => ForceState::On,
+        }
+    }
+}
+
+#[derive(Object)]
+struct SetForceReq {
+    /// whether charging is forced on or off
+    state: ForceStateReq,
+    /// id of the wattpilot (host and port of its url); not set = first wattpilot
+    id: Option<String>,
+}
 
 // -------------------------------------------------------------------------------------------------
 
@@ -50,6 +311,11 @@ pub(crate) struct SolarApi;
 #[derive(Tags)]
 enum Tag {
     Solar,
+    Inverter,
+    Influx,
+    Wattpilot,
+    Health,
+    Version,
 }
 
 #[OpenApi(prefix_path = "/api/solar", tag = "Tag::Solar")]
@@ -60,15 +326,335 @@ impl SolarApi {
         &self,
         state: Data<&AppState>,
     ) -> Result<SolarResp> {
-        Ok(
-            SolarResp::Ok(
-                Json(
-                    SolarRespData {
-                        wattpilot_data: state.wattpilot_data.read().await.clone(),
-                        solar_data: state.solar_data.read().await.clone(),
-                    }
-                )
-            )
-        )
+        Ok(SolarResp::Ok(Json(solar_resp_data(&state).await)))
+    }
+
+    /// get the highest power and the lowest battery charge of the current day
+    #[oai(path = "/stats", method = "get")]
+    async fn get_stats(
+        &self,
+        state: Data<&AppState>,
+    ) -> Json<DailyStats> {
+        Json(state.daily_stats.read().await.clone())
+    }
+
+    /// get the moving averages of the produced power, the consumption and the grid over the last 1, 5 and 15 minutes
+    #[oai(path = "/averages", method = "get")]
+    async fn get_averages(
+        &self,
+        state: Data<&AppState>,
+    ) -> Json<RollingAverages> {
+        Json(state.rolling_samples.read().await.averages())
+    }
+
+    /// get the values of a field written to influx within a time range of at most 31 days;\
+    /// fields are named like in influx, e.g. `both` or `house_consumption`;\
+    /// with more than one wattpilot, every value of a `wp_*` field has the `wp_id` of its wattpilot
+    #[oai(path = "/history", method = "get")]
+    async fn get_history(
+        &self,
+        state: Data<&AppState>,
+        /// start of the time range
+        from: Query<OffsetDateTime>,
+        /// end of the time range
+        to: Query<OffsetDateTime>,
+        /// name of the field
+        field: Query<String>,
+        /// only values of this wattpilot; not set = values of all wattpilots
+        wp_id: Query<Option<String>>,
+    ) -> HistoryResp {
+        if !history::configured(&state.config) {
+            return HistoryResp::ServiceUnavailable(PlainText("Influx is not configured".to_owned()));
+        }
+        if let Err(err) = history::validate(&state.config, &field, *from, *to) {
+            return HistoryResp::BadRequest(PlainText(err.to_string()));
+        }
+        match history::query(&state.config, &state.client, &field, wp_id.as_deref(), *from, *to).await {
+            Ok(points) => HistoryResp::Ok(Json(points)),
+            Err(err) => {
+                error!("Could not query history: {err}");
+                HistoryResp::InternalServerError
+            }
+        }
+    }
+
+    /// get the current values of the inverter only, without the wattpilot data
+    #[oai(path = "/values", method = "get")]
+    async fn get_solar_values(
+        &self,
+        state: Data<&AppState>,
+    ) -> Json<SolarData> {
+        Json(state.solar_data.read().await.clone())
+    }
+}
+
+/// collect the current values of the whole system
+async fn solar_resp_data(state: &AppState) -> SolarRespData {
+    let mut wattpilots = HashMap::new();
+    for wp in state.wattpilots.iter() {
+        let id = wp.read().await.id.clone();
+        wattpilots.insert(id, WattpilotRespData {
+            status: Wattpilot::status(Some(wp), &state.config).await,
+            data: wp.read().await.data.read().await.clone(),
+        });
+    }
+    SolarRespData {
+        wattpilot_status: Wattpilot::status(state.wattpilots.first(), &state.config).await,
+        wattpilot_data: state.wattpilot_data.read().await.clone(),
+        wattpilots,
+        solar_data: state.solar_data.read().await.clone(),
+    }
+}
+
+pub(crate) struct StreamApi;
+
+#[OpenApi(prefix_path = "/api/stream", tag = "Tag::Solar")]
+impl StreamApi {
+    /// stream the current system values as server sent events, e.g. for `EventSource`;\
+    /// every event is a message with the same json as `GET /api/solar`, sent after every scheduled fetch;\
+    /// a comment is sent every 15 seconds to keep the connection alive
+    #[oai(path = "/", method = "get")]
+    #[allow(clippy::unused_async)]
+    async fn stream(
+        &self,
+        state: Data<&AppState>,
+    ) -> EventStream<BoxStream<'static, SolarRespData>> {
+        let receiver = state.updates.subscribe();
+        let values = stream::unfold((state.0.clone(), receiver), |(state, mut receiver)| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(()) => break,
+                    // only the latest values matter
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+            Some((solar_resp_data(&state).await, (state, receiver)))
+        });
+        EventStream::new(values.boxed()).keep_alive(STREAM_KEEP_ALIVE)
+    }
+}
+
+pub(crate) struct RefreshApi;
+
+#[OpenApi(prefix_path = "/api/refresh", tag = "Tag::Solar")]
+impl RefreshApi {
+    /// fetch the solar values right now, without writing them to the database;\
+    /// the values are stored like the ones of the scheduled fetch, whichever fetch finishes last wins
+    #[oai(path = "/", method = "post")]
+    async fn refresh(
+        &self,
+        state: Data<&AppState>,
+    ) -> RefreshResp {
+        let result = fetch_solar_values(&state).await;
+        match result {
+            Ok(Verdict::Accepted(_)) => RefreshResp::Ok(Json(state.solar_data.read().await.clone())),
+            Ok(Verdict::Dropped(message)) => RefreshResp::UnprocessableEntity(PlainText(message)),
+            Err(err) => {
+                error!("Could not refresh solar values: {err}");
+                RefreshResp::InternalServerError
+            }
+        }
+    }
+}
+
+pub(crate) struct InverterApi;
+
+#[OpenApi(prefix_path = "/api/inverter", tag = "Tag::Inverter")]
+impl InverterApi {
+    /// get the untouched response of the inverter from the last successful poll
+    #[oai(path = "/raw", method = "get")]
+    async fn get_raw(
+        &self,
+        state: Data<&AppState>,
+        /// index of the inverter in `inverter_url`; not set = first inverter
+        index: Query<Option<usize>>,
+    ) -> InverterRawResp {
+        match state.inverter_raw.read().await.get(index.unwrap_or_default()) {
+            None => InverterRawResp::NotFound,
+            Some(raw) => InverterRawResp::Ok(PlainText(raw.clone())),
+        }
+    }
+}
+
+pub(crate) struct InfluxApi;
+
+#[OpenApi(prefix_path = "/api/influx", tag = "Tag::Influx")]
+impl InfluxApi {
+    /// get the line which would be written to influx for the current values, without writing it;\
+    /// needs the api key, if one is set
+    #[oai(path = "/preview", method = "get")]
+    async fn get_preview(
+        &self,
+        state: Data<&AppState>,
+    ) -> Result<PlainText<String>> {
+        Ok(PlainText(point_line(&state.config, &current_point(&state).await)?))
+    }
+}
+
+pub(crate) struct HealthApi;
+
+#[OpenApi(prefix_path = "/api/health", tag = "Tag::Health")]
+impl HealthApi {
+    /// check whether the solar values and the data of all wattpilots are fresh, e.g. for liveness probes
+    #[oai(path = "/", method = "get")]
+    async fn get_health(
+        &self,
+        state: Data<&AppState>,
+    ) -> HealthResp {
+        let mut outdated = Vec::new();
+        let solar = state.solar_data.read().await.clone();
+        if !solar.is_fresh(&state.config) {
+            outdated.push("solar".to_owned());
+        }
+        for wp in state.wattpilots.iter() {
+            let read = wp.read().await;
+            if !read.authenticated || !read.data.read().await.is_fresh(&state.config) {
+                outdated.push(format!("wattpilot {}", read.id));
+            }
+        }
+        let data = HealthRespData { stale: outdated, inverter_clock_skew: solar.inverter_clock_skew };
+        if data.stale.is_empty() {
+            HealthResp::Ok(Json(data))
+        } else {
+            HealthResp::ServiceUnavailable(Json(data))
+        }
+    }
+}
+
+pub(crate) struct VersionApi;
+
+#[OpenApi(prefix_path = "/api/version", tag = "Tag::Version")]
+impl VersionApi {
+    /// get version and build information of the running server
+    #[oai(path = "/", method = "get")]
+    #[allow(clippy::unused_async)]
+    async fn get_version(&self) -> Json<VersionRespData> {
+        Json(VersionRespData {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            git_hash: env!("GIT_HASH").to_owned(),
+            git_branch: Some(env!("GIT_BRANCH").to_owned()).filter(|branch| !branch.is_empty()),
+            git_dirty: env!("GIT_DIRTY").parse().ok(),
+            build_time: env!("BUILD_TIMESTAMP").parse().ok()
+                .and_then(|timestamp| OffsetDateTime::from_unix_timestamp(timestamp).ok())
+                .unwrap_or(OffsetDateTime::UNIX_EPOCH),
+            arch: ARCH.to_owned(),
+        })
+    }
+}
+
+pub(crate) struct WattpilotApi;
+
+#[OpenApi(prefix_path = "/api/wattpilot", tag = "Tag::Wattpilot")]
+impl WattpilotApi {
+    /// get the connection state of all wattpilots
+    #[oai(path = "/status", method = "get")]
+    async fn get_status(
+        &self,
+        state: Data<&AppState>,
+    ) -> WattpilotConnectionResp {
+        let mut result = Vec::with_capacity(state.wattpilots.len());
+        for wp in state.wattpilots.iter() {
+            let read = wp.read().await;
+            result.push(WattpilotConnectionData {
+                id: read.id.clone(),
+                authenticated: read.authenticated,
+                last_updated: read.data.read().await.last_updated,
+                reconnect_count: read.reconnect_count,
+                last_error: read.last_error.clone(),
+            });
+        }
+        WattpilotConnectionResp::Ok(Json(result))
+    }
+
+    /// get the current values of a wattpilot only, without the solar data
+    #[oai(path = "/values", method = "get")]
+    async fn get_values(
+        &self,
+        state: Data<&AppState>,
+        /// id of the wattpilot (host and port of its url); not set = first wattpilot
+        id: Query<Option<String>>,
+    ) -> WattpilotValuesResp {
+        let Some(wp) = Wattpilot::find(&state.wattpilots, id.as_deref()).await else {
+            return match id.0 {
+                Some(id) => WattpilotValuesResp::NotFound(PlainText(format!("Unknown wattpilot {id}"))),
+                None => WattpilotValuesResp::ServiceUnavailable(PlainText("Wattpilot is not configured".to_owned())),
+            };
+        };
+        let data = wp.read().await.data.read().await.clone();
+        WattpilotValuesResp::Ok(Json(WattpilotRespData {
+            status: Wattpilot::status(Some(&wp), &state.config).await,
+            data,
+        }))
+    }
+
+    /// set the charging current of the wattpilot
+    #[oai(path = "/current", method = "post")]
+    async fn set_current(
+        &self,
+        state: Data<&AppState>,
+        req: Json<SetCurrentReq>,
+    ) -> SetCurrentResp {
+        if !(6..=16).contains(&req.amp) {
+            return SetCurrentResp::BadRequest(PlainText(format!("Current must be between 6 and 16 A, got {}", req.amp)));
+        }
+        let wattpilot = match authenticated_wattpilot(&state, req.id.as_deref()).await {
+            Ok(v) => v,
+            Err(err) => return err.into(),
+        };
+        let result = wattpilot.write().await.set_value("amp", json!(req.amp)).await;
+        match result {
+            Ok(()) => SetCurrentResp::Ok,
+            Err(err) => {
+                error!("Could not set wattpilot current: {err}");
+                SetCurrentResp::InternalServerError
+            }
+        }
+    }
+
+    /// force charging on or off, or let the wattpilot decide (neutral)
+    #[oai(path = "/force", method = "post")]
+    async fn set_force(
+        &self,
+        state: Data<&AppState>,
+        req: Json<SetForceReq>,
+    ) -> SetForceResp {
+        let wattpilot = match authenticated_wattpilot(&state, req.id.as_deref()).await {
+            Ok(v) => v,
+            Err(err) => return err.into(),
+        };
+        let force_state = ForceState::from(req.state);
+        if let Err(err) = wattpilot.write().await.set_value("frc", json!(force_state)).await {
+            error!("Could not set wattpilot force state: {err}");
+            return SetForceResp::InternalServerError;
+        }
+        // the wattpilot confirms with a status update
+        let data = Arc::clone(&wattpilot.read().await.data);
+        for _ in 0..FORCE_CONFIRM_TRIES {
+            if data.read().await.force_state == force_state {
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        let data = data.read().await;
+        SetForceResp::Ok(Json(SetForceRespData {
+            confirmed: data.force_state == force_state,
+            force_state: data.force_state,
+            model_status: data.model_status.clone(),
+        }))
+    }
+}
+
+/// find the wattpilot with the given id, if it can be controlled
+async fn authenticated_wattpilot(state: &AppState, id: Option<&str>) -> Result<Arc<RwLock<Wattpilot>>, Unavailable> {
+    let Some(wattpilot) = Wattpilot::find(&state.wattpilots, id).await else {
+        return Err(match id {
+            Some(id) => Unavailable::Unknown(id.to_owned()),
+            None => Unavailable::NotConfigured,
+        });
+    };
+    if !wattpilot.read().await.authenticated {
+        return Err(Unavailable::NotAuthenticated);
     }
+    Ok(wattpilot)
 }
@@ -0,0 +1,78 @@
+//! api key authentication
+
+use std::sync::Arc;
+
+use poem::http::{Method, StatusCode};
+use poem::{Endpoint, IntoResponse, Request, Response, Result};
+use serde_json::json;
+use subtle::ConstantTimeEq;
+
+use crate::config::Config;
+
+/// header carrying the api key
+const API_KEY_HEADER: &str = "X-API-Key";
+
+/// reading endpoints which always need the key, as they expose internals like the influx tags
+const PROTECTED_READS: [&str; 1] = ["/api/influx/preview"];
+
+/// check the api key of a request before passing it on;\
+/// mutating requests and `PROTECTED_READS` always need the key, other reading ones only with `api_key_protect_reads`
+pub(crate) async fn check_api_key<E: Endpoint>(next: Arc<E>, req: Request, config: Arc<Config>) -> Result<Response> {
+    let Some(api_key) = config.api_key.as_deref() else {
+        return next.call(req).await.map(IntoResponse::into_response);
+    };
+    let path = req.uri().path().trim_end_matches('/');
+    let reading = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+        && !PROTECTED_READS.iter().any(|protected| path.ends_with(protected));
+    // constant time comparison, so the key can not be guessed from the response times
+    let authorized = req.header(API_KEY_HEADER).is_some_and(|key| bool::from(key.as_bytes().ct_eq(api_key.as_bytes())));
+    if authorized || (reading && !config.api_key_protect_reads) {
+        return next.call(req).await.map(IntoResponse::into_response);
+    }
+    Ok(Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .content_type("application/json")
+        .body(json!({"error": format!("Missing or wrong {API_KEY_HEADER} header")}).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::{EndpointExt, Route, get, handler};
+
+    use super::*;
+
+    #[handler]
+    fn ok() -> &'static str {
+        "ok"
+    }
+
+    /// status of a request against a route protected by `check_api_key`
+    async fn status(config: Config, path: &str, key: Option<&str>) -> StatusCode {
+        let config = Arc::new(config);
+        let route = Route::new()
+            .at("/api/solar", get(ok))
+            .at("/solar/api/influx/preview", get(ok))
+            .at("/api/influx/preview", get(ok))
+            .around(move |next, req| check_api_key(next, req, config.clone()));
+        let mut request = Request::builder().uri_str(path);
+        if let Some(key) = key {
+            request = request.header(API_KEY_HEADER, key);
+        }
+        route.get_response(request.finish()).await.status()
+    }
+
+    #[tokio::test]
+    async fn preview_needs_the_key() {
+        let config = || Config { api_key: Some("secret".to_owned()), ..Config::default() };
+        assert_eq!(status(config(), "/api/solar", None).await, StatusCode::OK);
+        assert_eq!(status(config(), "/api/influx/preview", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(config(), "/api/influx/preview/", Some("wrong")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(config(), "/solar/api/influx/preview", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(config(), "/api/influx/preview", Some("secret")).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn preview_is_open_without_key() {
+        assert_eq!(status(Config::default(), "/api/influx/preview", None).await, StatusCode::OK);
+    }
+}
@@ -0,0 +1,153 @@
+//! HTTP digest authentication (RFC 7616) for the inverter
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Result};
+use md5::Md5;
+use rand::random;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+/// how to authenticate against the inverter
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum InverterAuth {
+    /// no authentication
+    None,
+    /// http digest authentication
+    Digest,
+}
+
+/// split the parameters of a `WWW-Authenticate` header, respecting quoted values
+fn parse_challenge(header: &str) -> Result<HashMap<String, String>> {
+    let (scheme, params) = header.trim().split_once(' ').unwrap_or((header.trim(), ""));
+    if !scheme.eq_ignore_ascii_case("digest") {
+        bail!("Unsupported authentication scheme: {scheme}");
+    }
+    let mut result = HashMap::new();
+    let mut rest = params.trim();
+    while !rest.is_empty() {
+        let (key, value) = rest.split_once('=').ok_or_else(|| anyhow!("Invalid digest challenge: {header}"))?;
+        let value = value.trim_start();
+        let (value, remaining) = if let Some(quoted) = value.strip_prefix('"') {
+            let end = quoted.find('"').ok_or_else(|| anyhow!("Invalid digest challenge: {header}"))?;
+            (&quoted[..end], &quoted[end + 1..])
+        } else {
+            value.split_once(',').map_or((value, ""), |(v, r)| (v, r))
+        };
+        result.insert(key.trim().to_lowercase(), value.trim().to_owned());
+        rest = remaining.trim_start().trim_start_matches(',').trim_start();
+    }
+    Ok(result)
+}
+
+/// hash the given value with the algorithm of the challenge
+fn hash(algorithm: &str, value: &str) -> String {
+    if algorithm.eq_ignore_ascii_case("SHA-256") {
+        format!("{:x}", Sha256::digest(value))
+    } else {
+        format!("{:x}", Md5::digest(value))
+    }
+}
+
+/// build the `Authorization` header answering the `WWW-Authenticate` challenge of the server
+pub(crate) fn authorization(challenge: &str, method: &str, url: &Url, username: &str, password: &str) -> Result<String> {
+    answer(challenge, method, url, username, password, &format!("{:016x}", random::<u64>()))
+}
+
+/// build the `Authorization` header with the given client nonce, used if the server asks for `qop=auth`
+fn answer(challenge: &str, method: &str, url: &Url, username: &str, password: &str, cnonce: &str) -> Result<String> {
+    let params = parse_challenge(challenge)?;
+    let realm = params.get("realm").map_or("", String::as_str);
+    let nonce = params.get("nonce").ok_or_else(|| anyhow!("Digest challenge without nonce"))?;
+    let algorithm = params.get("algorithm").map_or("MD5", String::as_str);
+    if !["MD5", "SHA-256"].iter().any(|a| a.eq_ignore_ascii_case(algorithm)) {
+        bail!("Unsupported digest algorithm: {algorithm}");
+    }
+    let uri = match url.query() {
+        None => url.path().to_owned(),
+        Some(query) => format!("{}?{query}", url.path()),
+    };
+    let ha1 = hash(algorithm, &format!("{username}:{realm}:{password}"));
+    let ha2 = hash(algorithm, &format!("{method}:{uri}"));
+    let mut parts = vec![
+        format!(r#"username="{username}""#),
+        format!(r#"realm="{realm}""#),
+        format!(r#"nonce="{nonce}""#),
+        format!(r#"uri="{uri}""#),
+        format!("algorithm={algorithm}"),
+    ];
+    if params.get("qop").is_some_and(|qop| qop.split(',').any(|q| q.trim() == "auth")) {
+        let response = hash(algorithm, &format!("{ha1}:{nonce}:00000001:{cnonce}:auth:{ha2}"));
+        parts.extend([
+            "qop=auth".to_owned(),
+            "nc=00000001".to_owned(),
+            format!(r#"cnonce="{cnonce}""#),
+            format!(r#"response="{response}""#),
+        ]);
+    } else {
+        let response = hash(algorithm, &format!("{ha1}:{nonce}:{ha2}"));
+        parts.push(format!(r#"response="{response}""#));
+    }
+    if let Some(opaque) = params.get("opaque") {
+        parts.push(format!(r#"opaque="{opaque}""#));
+    }
+    Ok(format!("Digest {}", parts.join(", ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answer_rfc_2617_example() -> Result<()> {
+        let challenge = r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#;
+        let url = Url::parse("http://www.nowhere.org/dir/index.html")?;
+        let header = answer(challenge, "GET", &url, "Mufasa", "Circle Of Life", "0a4f113b")?;
+        assert_eq!(header, concat!(
+            r#"Digest username="Mufasa", realm="testrealm@host.com", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", "#,
+            r#"uri="/dir/index.html", algorithm=MD5, qop=auth, nc=00000001, cnonce="0a4f113b", "#,
+            r#"response="6629fae49393a05397450978507c4ef1", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn answer_rfc_7616_examples() -> Result<()> {
+        let url = Url::parse("http://www.example.org/dir/index.html")?;
+        let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+        let challenge = |algorithm: &str| format!(
+            r#"Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm={algorithm}, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#
+        );
+        let sha256 = answer(&challenge("SHA-256"), "GET", &url, "Mufasa", "Circle of Life", cnonce)?;
+        assert!(sha256.contains(r#"response="753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1""#));
+        let md5 = answer(&challenge("MD5"), "GET", &url, "Mufasa", "Circle of Life", cnonce)?;
+        assert!(md5.contains(r#"response="8ca523f5e9506fed4657c9700eebdbec""#));
+        Ok(())
+    }
+
+    #[test]
+    fn answer_without_qop() -> Result<()> {
+        let url = Url::parse("http://inverter/status/powerflow?a=1")?;
+        let header = answer(r#"Digest realm="inverter", nonce="abc""#, "POST", &url, "admin", "secret", "unused")?;
+        let expected = hash("MD5", &format!(
+            "{}:abc:{}",
+            hash("MD5", "admin:inverter:secret"),
+            hash("MD5", "POST:/status/powerflow?a=1"),
+        ));
+        assert!(header.contains(r#"uri="/status/powerflow?a=1""#));
+        assert!(header.ends_with(&format!(r#"response="{expected}""#)));
+        assert!(!header.contains("cnonce"));
+        Ok(())
+    }
+
+    #[test]
+    fn answer_rejects_other_challenges() -> Result<()> {
+        let url = Url::parse("http://inverter")?;
+        assert!(answer(r#"Basic realm="inverter""#, "GET", &url, "a", "b", "c").is_err());
+        assert!(answer(r#"Digest realm="inverter""#, "GET", &url, "a", "b", "c").is_err());
+        assert!(answer(r#"Digest nonce="abc", algorithm=SHA-512"#, "GET", &url, "a", "b", "c").is_err());
+        Ok(())
+    }
+}
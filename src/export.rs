@@ -0,0 +1,35 @@
+//! csv export of the current values
+
+use poem::http::header::CONTENT_DISPOSITION;
+use poem::web::{Data, Query};
+use poem::{handler, Response};
+use serde::Deserialize;
+use time::format_description::well_known::Rfc3339;
+
+use crate::AppState;
+use crate::influx::numeric_fields;
+
+#[derive(Deserialize)]
+pub(crate) struct CsvParams {
+    /// also export the fields of the first wattpilot
+    #[serde(default)]
+    wattpilot: bool,
+}
+
+/// get the current values as a csv file with a header line and one row;\
+/// the columns have the order of the influx fields
+#[handler]
+pub(crate) async fn get_solar_csv(state: Data<&AppState>, Query(params): Query<CsvParams>) -> Response {
+    let solar = state.solar_data.read().await.clone();
+    let wp = state.wattpilot_data.read().await.clone();
+    let fields: Vec<(&str, f64)> = numeric_fields(&solar, &wp).into_iter()
+        .filter(|(name, _)| params.wattpilot || !name.starts_with("wp_"))
+        .collect();
+    let time = solar.last_time.format(&Rfc3339).unwrap_or_default();
+    let header = fields.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(",");
+    let row = fields.iter().map(|(_, value)| value.to_string()).collect::<Vec<_>>().join(",");
+    Response::builder()
+        .content_type("text/csv; charset=utf-8")
+        .header(CONTENT_DISPOSITION, "attachment; filename=\"solar.csv\"")
+        .body(format!("time,{header}\r\n{time},{row}\r\n"))
+}
@@ -0,0 +1,228 @@
+//! historical values, queried from influx
+
+use anyhow::{anyhow, bail, Result};
+use poem_openapi::Object;
+use reqwest::header::CONTENT_TYPE;
+use serde_json::Value;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::aggregate::parse_aggregations;
+use crate::config::Config;
+use crate::influx::{authorize, endpoint, InfluxVersion};
+
+/// numeric fields written to influx, which can be queried
+const FIELDS: [&str; 29] = [
+    "old", "new", "both", "energy_day", "energy_year", "energy_total", "battery_temperature",
+    "battery_percentage", "autonomy_percentage", "self_consumption_percentage", "self_sufficiency_percentage",
+    "drain_from_battery", "drain_from_grid", "house_consumption", "grid_import", "grid_export",
+    "solar_coverage_percentage", "grid_u1", "grid_u2", "grid_u3", "grid_i1", "grid_i2", "grid_i3",
+    "wp_wh", "wp_power", "wp_p1", "wp_p2", "wp_p3", "wp_total_energy",
+];
+
+/// longest time range of one query; data in days
+const MAX_RANGE_DAYS: i64 = 31;
+
+/// one value of a field
+#[derive(Object, Debug, Clone)]
+pub struct HistoryPoint {
+    /// time of the value
+    pub(crate) time: OffsetDateTime,
+    /// value of the field
+    pub(crate) value: f64,
+    /// wattpilot of the value; only set for `wp_*` fields with more than one wattpilot
+    pub(crate) wp_id: Option<String>,
+}
+
+/// check field and time range of a query; fields are named like in influx, with the aggregation appended
+/// (e.g. `both_max`) if `influx_aggregate_window_secs` is set
+pub(crate) fn validate(config: &Config, field: &str, from: OffsetDateTime, to: OffsetDateTime) -> Result<()> {
+    let aggregations = parse_aggregations(&config.influx_aggregations)?;
+    let known = if config.influx_aggregate_window_secs > 0 {
+        FIELDS.iter().any(|name| {
+            aggregations.iter().any(|aggregation| field == format!("{name}_{}", aggregation.name()))
+        })
+    } else {
+        FIELDS.contains(&field)
+    };
+    if !known {
+        bail!("Unknown field {field}");
+    }
+    if from >= to {
+        bail!("From should be before to");
+    }
+    if to - from > time::Duration::days(MAX_RANGE_DAYS) {
+        bail!("Time range should be at most {MAX_RANGE_DAYS} days");
+    }
+    Ok(())
+}
+
+/// value of `influx_org`/`influx_bucket`, else of the query parameter of `influx_url`
+fn setting(config: &Config, configured: Option<&String>, name: &str) -> Option<String> {
+    configured.cloned().or_else(|| {
+        config.influx_url.as_ref()?.query_pairs().find(|(key, _)| key == name).map(|(_, value)| value.into_owned())
+    })
+}
+
+/// whether influx is configured well enough to be queried
+pub(crate) fn configured(config: &Config) -> bool {
+    config.influx_url.is_some() && match config.influx_version {
+        InfluxVersion::V2 => setting(config, config.influx_org.as_ref(), "org").is_some()
+            && setting(config, config.influx_bucket.as_ref(), "bucket").is_some(),
+        InfluxVersion::V1 => setting(config, config.influx_bucket.as_ref(), "db").is_some(),
+    }
+}
+
+/// escape a string literal of a flux or influxql query
+fn escape_literal(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\'', "\\'")
+}
+
+/// query the values of a validated field within the time range, oldest first;\
+/// values of all wattpilots are returned with their `wp_id`, unless it is given
+pub(crate) async fn query(
+    config: &Config,
+    client: &reqwest::Client,
+    field: &str,
+    wp_id: Option<&str>,
+    from: OffsetDateTime,
+    to: OffsetDateTime,
+) -> Result<Vec<HistoryPoint>> {
+    let url = config.influx_url.as_ref().ok_or_else(|| anyhow!("Influx url is not set"))?;
+    let measurement = escape_literal(config.influx_measurement.as_deref().unwrap_or_default());
+    let (from, to) = (from.format(&Rfc3339)?, to.format(&Rfc3339)?);
+    let mut points = match config.influx_version {
+        InfluxVersion::V2 => {
+            let org = setting(config, config.influx_org.as_ref(), "org")
+                .ok_or_else(|| anyhow!("Influx org is not set"))?;
+            let bucket = setting(config, config.influx_bucket.as_ref(), "bucket")
+                .ok_or_else(|| anyhow!("Influx bucket is not set"))?;
+            let mut url = endpoint(url, "api/v2/query")?;
+            url.query_pairs_mut().append_pair("org", &org);
+            let wp_filter = wp_id.map(|id| format!(" and r.wp_id == \"{}\"", escape_literal(id))).unwrap_or_default();
+            let flux = format!(
+                "from(bucket: \"{}\") |> range(start: {from}, stop: {to}) \
+                 |> filter(fn: (r) => r._measurement == \"{measurement}\" and r._field == \"{field}\"{wp_filter}) \
+                 |> keep(columns: [\"_time\", \"_value\", \"wp_id\"])",
+                escape_literal(&bucket),
+            );
+            let body = serde_json::json!({"query": flux, "type": "flux", "dialect": {"annotations": []}});
+            let request = client.post(url).header(CONTENT_TYPE, "application/json").body(body.to_string());
+            parse_csv(&send(config, request).await?)?
+        }
+        InfluxVersion::V1 => {
+            let db = setting(config, config.influx_bucket.as_ref(), "db")
+                .ok_or_else(|| anyhow!("Influx database is not set"))?;
+            let mut url = endpoint(url, "query")?;
+            let wp_filter = wp_id.map(|id| format!(" AND \"wp_id\" = '{}'", escape_literal(id))).unwrap_or_default();
+            url.query_pairs_mut()
+                .append_pair("db", &db)
+                .append_pair("epoch", "s")
+                .append_pair("q", &format!(
+                    "SELECT \"{field}\" FROM \"{measurement}\" WHERE time >= '{from}' AND time <= '{to}'{wp_filter} \
+                     GROUP BY \"wp_id\""
+                ));
+            parse_json(&send(config, client.get(url)).await?)?
+        }
+    };
+    points.sort_by_key(|point| point.time);
+    Ok(points)
+}
+
+/// send an authorized query and get the response body
+async fn send(config: &Config, request: reqwest::RequestBuilder) -> Result<String> {
+    let resp = authorize(config, request).send().await?;
+    if !resp.status().is_success() {
+        bail!("Influx query failed: {}, {}", resp.status(), resp.text().await?);
+    }
+    Ok(resp.text().await?)
+}
+
+/// parse the csv response of a flux query; every table starts with its own header
+fn parse_csv(body: &str) -> Result<Vec<HistoryPoint>> {
+    let mut columns: Vec<&str> = Vec::new();
+    let mut points = Vec::new();
+    for line in body.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let values: Vec<&str> = line.split(',').collect();
+        if values.contains(&"_time") && values.contains(&"_value") {
+            columns = values;
+            continue;
+        }
+        let get = |name: &str| columns.iter().position(|column| *column == name)
+            .and_then(|position| values.get(position).copied());
+        let time = get("_time").ok_or_else(|| anyhow!("Invalid influx row {line}"))?;
+        let value = get("_value").ok_or_else(|| anyhow!("Invalid influx row {line}"))?;
+        points.push(HistoryPoint {
+            time: OffsetDateTime::parse(time, &Rfc3339)?,
+            value: value.parse()?,
+            wp_id: get("wp_id").filter(|id| !id.is_empty()).map(str::to_owned),
+        });
+    }
+    Ok(points)
+}
+
+/// parse the json response of an influxql query with `epoch=s`, grouped by `wp_id`
+fn parse_json(body: &str) -> Result<Vec<HistoryPoint>> {
+    let json: Value = serde_json::from_str(body)?;
+    let all_series = json["results"].as_array().into_iter().flatten()
+        .flat_map(|result| result["series"].as_array().into_iter().flatten());
+    let mut points = Vec::new();
+    for series in all_series {
+        let wp_id = series["tags"]["wp_id"].as_str().filter(|id| !id.is_empty());
+        for row in series["values"].as_array().into_iter().flatten() {
+            let (Some(time), Some(value)) = (row[0].as_i64(), row[1].as_f64()) else {
+                continue;
+            };
+            points.push(HistoryPoint {
+                time: OffsetDateTime::from_unix_timestamp(time)?,
+                value,
+                wp_id: wp_id.map(str::to_owned),
+            });
+        }
+    }
+    Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::*;
+
+    #[test]
+    fn csv_tables_per_wattpilot() -> Result<()> {
+        let body = ",result,table,_time,_value,wp_id\n\
+            ,_result,0,2024-06-01T12:00:00Z,1000,wp1\n\
+            ,_result,0,2024-06-01T12:00:10Z,1100,wp1\n\
+            \n\
+            ,result,table,_time,_value,wp_id\n\
+            ,_result,1,2024-06-01T12:00:00Z,500,wp2\n";
+        let points = parse_csv(body)?;
+        let ids: Vec<Option<&str>> = points.iter().map(|point| point.wp_id.as_deref()).collect();
+        assert_eq!(ids, [Some("wp1"), Some("wp1"), Some("wp2")]);
+        assert!((points[2].value - 500.0).abs() < f64::EPSILON);
+        assert!(parse_csv(",result,table,_time,_value\n,_result,0,2024-06-01T12:00:00Z,1\n")?[0].wp_id.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn json_series_per_wattpilot() -> Result<()> {
+        let body = r#"{"results": [{"series": [
+            {"name": "solar", "tags": {"wp_id": "wp1"}, "columns": ["time", "wp_power"], "values": [[1717243200, 1000]]},
+            {"name": "solar", "tags": {"wp_id": "wp2"}, "columns": ["time", "wp_power"], "values": [[1717243200, 500]]},
+            {"name": "solar", "tags": {"wp_id": ""}, "columns": ["time", "wp_power"], "values": [[1717243210, 0]]}
+        ]}]}"#;
+        let ids: Vec<Option<String>> = parse_json(body)?.into_iter().map(|point| point.wp_id).collect();
+        assert_eq!(ids, [Some("wp1".to_owned()), Some("wp2".to_owned()), None]);
+        Ok(())
+    }
+
+    #[test]
+    fn configured_needs_url_and_target() -> Result<()> {
+        assert!(!configured(&Config::default()));
+        let url = Some(Url::parse("http://influx:8086/write?db=solar")?);
+        assert!(configured(&Config { influx_url: url.clone(), influx_version: InfluxVersion::V1, ..Config::default() }));
+        assert!(!configured(&Config { influx_url: url, influx_version: InfluxVersion::V2, ..Config::default() }));
+        Ok(())
+    }
+}
@@ -0,0 +1,991 @@
+//! Writing data points to influx
+
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use futures_util::future::BoxFuture;
+use poem::http::header::{AUTHORIZATION, CONTENT_ENCODING};
+use poem_openapi::types::ToJSON;
+use reqwest::{RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+use url::Url;
+
+use crate::aggregate::{Aggregation, collect_fields, parse_aggregations};
+use crate::config::Config;
+use crate::inverter::SolarData;
+use crate::sink::{Point, Sink};
+use crate::wattpilot::WattpilotData;
+
+/// write api of the influx database
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum InfluxVersion {
+    /// `/write`, basic authentication
+    V1,
+    /// `/api/v2/write`, token authentication
+    V2,
+}
+
+/// enum fields which can be written as label tags
+pub(crate) const LABEL_TAG_FIELDS: [&str; 2] = ["car_state", "model_status"];
+
+/// escape a measurement for the influx line protocol
+fn escape_measurement(value: &str) -> String {
+    value.replace(',', "\\,").replace(' ', "\\ ")
+}
+
+/// escape a tag key or value for the influx line protocol
+fn escape_tag(value: &str) -> String {
+    value.replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+/// escape a string field value for the influx line protocol
+fn escape_string(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// url of an influx endpoint like `api/v2/write`, relative to the path of `influx_url`;\
+/// a write endpoint in `influx_url` is removed first, so the path prefix of a reverse proxy is kept
+pub(crate) fn endpoint(url: &Url, path: &str) -> Result<Url> {
+    let mut base = url.clone();
+    base.set_query(None);
+    let prefix = base.path().trim_end_matches('/').to_owned();
+    let prefix = ["/api/v2/write", "/write"].iter()
+        .find_map(|write| prefix.strip_suffix(write))
+        .unwrap_or(&prefix)
+        .to_owned();
+    base.set_path(&format!("{prefix}/"));
+    Ok(base.join(path)?)
+}
+
+/// get the write url; built from the base url, org and bucket if they are set, else the raw influx url is used;\
+/// a precision in the raw url is kept
+fn write_url(config: &Config) -> Result<Url> {
+    let url = config.influx_url.as_ref().ok_or_else(|| anyhow!("Influx url is not set"))?;
+    let mut url = match (&config.influx_org, &config.influx_bucket) {
+        (Some(org), Some(bucket)) => {
+            let mut url = endpoint(url, "api/v2/write")?;
+            url.query_pairs_mut().append_pair("org", org).append_pair("bucket", bucket);
+            url
+        }
+        _ => url.clone(),
+    };
+    if !url.query_pairs().any(|(key, _)| key == "precision") {
+        url.query_pairs_mut().append_pair("precision", config.influx_precision.name());
+    }
+    Ok(url)
+}
+
+/// add the influx credentials to a request; a token for v2, `username:password` as basic authentication for v1
+pub(crate) fn authorize(config: &Config, request: RequestBuilder) -> RequestBuilder {
+    match (config.influx_version, &config.influx_token) {
+        (InfluxVersion::V2, token) => {
+            request.header(AUTHORIZATION, format!("Token {}", token.as_deref().unwrap_or_default()))
+        }
+        (InfluxVersion::V1, Some(token)) => {
+            let (username, password) = token.split_once(':').unwrap_or((token, ""));
+            request.basic_auth(username, Some(password))
+        }
+        (InfluxVersion::V1, None) => request,
+    }
+}
+
+/// gzip the line protocol body
+fn gzip(body: &str) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body.as_bytes())?;
+    Ok(encoder.finish()?)
+}
+
+/// get the label of an enum value as shown in the api
+fn enum_label<T: ToJSON>(value: &T) -> String {
+    value.to_json()
+        .and_then(|json| json.as_str().map(str::to_owned))
+        .unwrap_or_default()
+}
+
+/// get the label tags for the configured enum fields
+pub(crate) fn label_tags(config: &Config, wp: &WattpilotData) -> Vec<(String, String)> {
+    config.influx_label_tags.split(',').map(str::trim).filter_map(|field| {
+        let label = match field {
+            "car_state" => enum_label(&wp.car_state),
+            "model_status" => enum_label(&wp.model_status),
+            _ => return None,
+        };
+        Some((format!("{field}_label"), label))
+    }).collect()
+}
+
+/// parse the static tags, e.g. `site=home,location=garage`
+pub(crate) fn parse_tags(tags: &str) -> Result<Vec<(String, String)>> {
+    tags.split(',').map(str::trim).filter(|s| !s.is_empty()).map(|tag| {
+        let (key, value) = tag.split_once('=')
+            .ok_or_else(|| anyhow!("Influx tag {tag} should look like key=value"))?;
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty() || value.is_empty() {
+            bail!("Influx tag {tag} should have a non empty key and value");
+        }
+        Ok((key.to_owned(), value.to_owned()))
+    }).collect()
+}
+
+/// get the static tags and the label tags for the given wattpilot values
+fn point_tags(config: &Config, wp: &WattpilotData) -> Vec<(String, String)> {
+    // validated at startup
+    let mut tags = parse_tags(&config.influx_tags).unwrap_or_default();
+    tags.extend(label_tags(config, wp));
+    tags
+}
+
+/// format tags for the line protocol, including the leading comma
+fn tag_string(tags: &[(String, String)]) -> String {
+    tags.iter()
+        .map(|(key, value)| format!(",{}={}", escape_tag(key), escape_tag(value)))
+        .collect::<Vec<_>>()
+        .concat()
+}
+
+/// fields of the line protocol line for the solar values
+fn solar_fields(solar: &SolarData) -> String {
+    let inverter_fields = [
+        solar.old_inverter_power.map(|power| format!("old={power}")),
+        Some(format!("new={}", solar.new_inverter_power)),
+        solar.both_inverter_power.map(|power| format!("both={power}")),
+        solar.energy_day.map(|energy| format!("energy_day={energy}")),
+        solar.energy_year.map(|energy| format!("energy_year={energy}")),
+        solar.energy_total.map(|energy| format!("energy_total={energy}")),
+        solar.battery_temperature.map(|temperature| format!("battery_temperature={temperature}")),
+        solar.inverter_status_code.map(|code| format!("inverter_status_code={code}")),
+        solar.inverter_error_code.map(|code| format!("inverter_error_code={code}")),
+    ].into_iter().flatten().collect::<Vec<_>>().join(",");
+    let grid_phase_fields = solar.grid_phases.as_ref().map(|phases| format!(
+        ",grid_u1={},grid_u2={},grid_u3={},grid_i1={},grid_i2={},grid_i3={}",
+        phases.u1, phases.u2, phases.u3, phases.i1, phases.i2, phases.i3,
+    )).unwrap_or_default();
+    format!(
+        "{},battery_percentage={},autonomy_percentage={},self_consumption_percentage={},self_sufficiency_percentage={},drain_from_battery={},drain_from_grid={},house_consumption={},grid_import={},grid_export={},solar_coverage_percentage={}{}",
+        inverter_fields,
+        solar.battery_load_percentage,
+        solar.autonomy_percent,
+        solar.self_consumption_percent,
+        solar.self_sufficiency_percent,
+        solar.drain_from_battery,
+        solar.drain_from_grid,
+        solar.house_consumption,
+        solar.grid_import_watts,
+        solar.grid_export_watts,
+        solar.solar_coverage_percent,
+        grid_phase_fields,
+    )
+}
+
+/// fields of the line protocol line for the values of one wattpilot
+fn wattpilot_fields(wp: &WattpilotData, current_delta: bool) -> Result<String> {
+    let extra_fields = if wp.extra.is_empty() {
+        String::new()
+    } else {
+        format!(",wp_extra=\"{}\"", escape_string(&serde_json::to_string(&wp.extra)?))
+    };
+    let current_delta_fields = if current_delta {
+        format!(",wp_amp={},wp_current_delta={},wp_car_limited={}", wp.amp, wp.current_delta, wp.car_limited)
+    } else {
+        String::new()
+    };
+    Ok(format!(
+        "wp_charging_values=\"{}\",wp_car_state={},wp_model_status={},wp_wh={},wp_tpcm=\"{}\",wp_lps={},wp_ets={},wp_power={},wp_p1={},wp_p2={},wp_p3={},wp_force_state={},wp_phase_mode={},wp_total_energy={},wp_cable_limit={}{}{}",
+        escape_string(&serde_json::to_string(&wp.charging_values)?),
+        serde_json::to_string(&wp.car_state)?,
+        serde_json::to_string(&wp.model_status)?,
+        wp.charged_since_connected,
+        escape_string(&wp.tpcm),
+        wp.lps,
+        wp.ets,
+        wp.charging_values.pt,
+        wp.charging_values.p1,
+        wp.charging_values.p2,
+        wp.charging_values.p3,
+        serde_json::to_string(&wp.force_state)?,
+        serde_json::to_string(&wp.phase_mode)?,
+        wp.total_energy_wh,
+        wp.cable_limit_amp,
+        current_delta_fields,
+        extra_fields,
+    ))
+}
+
+/// build the line protocol line for the given values; timestamp in units of the influx precision
+pub(crate) fn build_line(
+    measurement: &str,
+    tags: &[(String, String)],
+    solar: &SolarData,
+    wp: &WattpilotData,
+    current_delta: bool,
+    timestamp: i128,
+) -> Result<String> {
+    Ok(format!(
+        "{}{} {},{} {timestamp}",
+        escape_measurement(measurement),
+        tag_string(tags),
+        solar_fields(solar),
+        wattpilot_fields(wp, current_delta)?,
+    ))
+}
+
+/// build the line protocol lines for a point, using measurement and tags from the config;\
+/// with more than one wattpilot, the solar values and every wattpilot get their own line, tagged with `wp_id`
+pub(crate) fn point_line(config: &Config, point: &Point) -> Result<String> {
+    let measurement = config.influx_measurement.as_deref()
+        .ok_or_else(|| anyhow!("Influx measurement is not set"))?;
+    let timestamp = config.influx_precision.timestamp(point.time);
+    if point.wattpilots.len() <= 1 {
+        return build_line(
+            measurement,
+            &point_tags(config, &point.wattpilot),
+            &point.solar,
+            &point.wattpilot,
+            config.influx_current_delta,
+            timestamp,
+        );
+    }
+    let measurement = escape_measurement(measurement);
+    let static_tags = parse_tags(&config.influx_tags).unwrap_or_default();
+    let mut lines = vec![format!(
+        "{measurement}{} {} {timestamp}",
+        tag_string(&static_tags),
+        solar_fields(&point.solar),
+    )];
+    for (id, wp) in &point.wattpilots {
+        let mut tags = static_tags.clone();
+        tags.push(("wp_id".to_owned(), id.clone()));
+        tags.extend(label_tags(config, wp));
+        lines.push(format!(
+            "{measurement}{} {} {timestamp}",
+            tag_string(&tags),
+            wattpilot_fields(wp, config.influx_current_delta)?,
+        ));
+    }
+    Ok(lines.join("\n"))
+}
+
+/// numeric fields of the line protocol line, which can be summarized over a window
+pub(crate) fn numeric_fields(solar: &SolarData, wp: &WattpilotData) -> Vec<(&'static str, f64)> {
+    let mut fields = solar_numeric_fields(solar);
+    fields.extend(wattpilot_numeric_fields(wp));
+    fields
+}
+
+/// numeric fields of the solar values
+#[allow(clippy::cast_precision_loss)]
+fn solar_numeric_fields(solar: &SolarData) -> Vec<(&'static str, f64)> {
+    [
+        solar.old_inverter_power.map(|power| ("old", f64::from(power))),
+        Some(("new", f64::from(solar.new_inverter_power))),
+        solar.both_inverter_power.map(|power| ("both", f64::from(power))),
+        solar.energy_day.map(|energy| ("energy_day", energy as f64)),
+        solar.energy_year.map(|energy| ("energy_year", energy as f64)),
+        solar.energy_total.map(|energy| ("energy_total", energy as f64)),
+        solar.battery_temperature.map(|temperature| ("battery_temperature", temperature)),
+        Some(("battery_percentage", f64::from(solar.battery_load_percentage))),
+        Some(("autonomy_percentage", f64::from(solar.autonomy_percent))),
+        Some(("self_consumption_percentage", f64::from(solar.self_consumption_percent))),
+        Some(("self_sufficiency_percentage", f64::from(solar.self_sufficiency_percent))),
+        Some(("drain_from_battery", solar.drain_from_battery as f64)),
+        Some(("drain_from_grid", solar.drain_from_grid as f64)),
+        Some(("house_consumption", solar.house_consumption as f64)),
+        Some(("grid_import", solar.grid_import_watts as f64)),
+        Some(("grid_export", solar.grid_export_watts as f64)),
+        Some(("solar_coverage_percentage", f64::from(solar.solar_coverage_percent))),
+        solar.grid_phases.as_ref().map(|phases| ("grid_u1", phases.u1)),
+        solar.grid_phases.as_ref().map(|phases| ("grid_u2", phases.u2)),
+        solar.grid_phases.as_ref().map(|phases| ("grid_u3", phases.u3)),
+        solar.grid_phases.as_ref().map(|phases| ("grid_i1", phases.i1)),
+        solar.grid_phases.as_ref().map(|phases| ("grid_i2", phases.i2)),
+        solar.grid_phases.as_ref().map(|phases| ("grid_i3", phases.i3)),
+    ].into_iter().flatten().collect()
+}
+
+/// numeric fields of the values of one wattpilot
+#[allow(clippy::cast_precision_loss)]
+fn wattpilot_numeric_fields(wp: &WattpilotData) -> Vec<(&'static str, f64)> {
+    vec![
+        ("wp_wh", wp.charged_since_connected),
+        ("wp_power", f64::from(wp.charging_values.pt)),
+        ("wp_p1", f64::from(wp.charging_values.p1)),
+        ("wp_p2", f64::from(wp.charging_values.p2)),
+        ("wp_p3", f64::from(wp.charging_values.p3)),
+        ("wp_total_energy", wp.total_energy_wh as f64),
+    ]
+}
+
+/// summarize the numeric fields of several samples with the given functions, formatted for the line protocol
+fn summarized_fields(aggregations: &[Aggregation], samples: Vec<Vec<(&'static str, f64)>>) -> String {
+    let count = samples.len();
+    let fields = collect_fields(samples)
+        .into_iter()
+        .flat_map(|(name, values)| {
+            aggregations.iter()
+                .filter_map(|aggregation| {
+                    aggregation.apply(&values).map(|value| format!("{name}_{}={value}", aggregation.name()))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{fields},samples={count}i")
+}
+
+/// build the lines summarizing the points of a window, using measurement and tags from the config;
+/// timestamp in seconds\
+/// like `point_line`, with more than one wattpilot the solar values and every wattpilot get their own line
+fn aggregate_line(config: &Config, points: &[Point], timestamp: i64) -> Result<String> {
+    let timestamp = config.influx_precision.timestamp(OffsetDateTime::from_unix_timestamp(timestamp)?);
+    let measurement = config.influx_measurement.as_deref()
+        .ok_or_else(|| anyhow!("Influx measurement is not set"))?;
+    let measurement = escape_measurement(measurement);
+    let last = points.last().ok_or_else(|| anyhow!("No points to aggregate"))?;
+    let aggregations = parse_aggregations(&config.influx_aggregations)?;
+    if last.wattpilots.len() <= 1 {
+        let tags = tag_string(&point_tags(config, &last.wattpilot));
+        let samples = points.iter().map(|point| numeric_fields(&point.solar, &point.wattpilot)).collect();
+        return Ok(format!("{measurement}{tags} {} {timestamp}", summarized_fields(&aggregations, samples)));
+    }
+    let static_tags = parse_tags(&config.influx_tags).unwrap_or_default();
+    let solar_samples = points.iter().map(|point| solar_numeric_fields(&point.solar)).collect();
+    let mut lines = vec![format!(
+        "{measurement}{} {} {timestamp}",
+        tag_string(&static_tags),
+        summarized_fields(&aggregations, solar_samples),
+    )];
+    for (id, wp) in &last.wattpilots {
+        let samples = points.iter()
+            .filter_map(|point| point.wattpilots.iter().find(|(other, _)| other == id))
+            .map(|(_, data)| wattpilot_numeric_fields(data))
+            .collect();
+        let mut tags = static_tags.clone();
+        tags.push(("wp_id".to_owned(), id.clone()));
+        tags.extend(label_tags(config, wp));
+        lines.push(format!(
+            "{measurement}{} {} {timestamp}",
+            tag_string(&tags),
+            summarized_fields(&aggregations, samples),
+        ));
+    }
+    Ok(lines.join("\n"))
+}
+
+/// why a write to influx failed
+#[derive(Debug)]
+enum WriteError {
+    /// influx rejected the lines, e.g. because one is malformed; sending them again does not help
+    Rejected(anyhow::Error),
+    /// influx is not reachable, overloaded or failed internally; the lines are sent again later
+    Retryable(anyhow::Error),
+}
+
+/// first line of the buffer file, the buffered timestamps are in this precision;\
+/// a comment in the line protocol
+fn buffer_header(config: &Config) -> String {
+    format!("# precision={}", config.influx_precision.name())
+}
+
+/// writes points to influx (v2 write api)
+pub(crate) struct InfluxSink {
+    config: Config,
+    client: reqwest::Client,
+    /// points of the current aggregation window
+    window: Mutex<Vec<Point>>,
+    /// lines which were not written yet, and when the oldest one was added
+    buffer: Mutex<(Vec<String>, Option<Instant>)>,
+    /// number of lines in the buffer
+    pending: AtomicUsize,
+}
+
+impl InfluxSink {
+    pub(crate) fn new(config: &Config, client: &reqwest::Client) -> Self {
+        let lines = match &config.influx_buffer_file {
+            None => Vec::new(),
+            Some(path) => match fs::read_to_string(path) {
+                Ok(content) => {
+                    let mut lines = content.lines().filter(|line| !line.is_empty());
+                    if lines.next() == Some(buffer_header(config).as_str()) {
+                        lines.map(str::to_owned).collect()
+                    } else {
+                        warn!(
+                            "Discarding influx buffer file {path}, its lines were not written with precision {}",
+                            config.influx_precision.name(),
+                        );
+                        Vec::new()
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+                Err(err) => {
+                    error!("Could not read influx buffer file {path}: {err}");
+                    Vec::new()
+                }
+            },
+        };
+        if !lines.is_empty() {
+            info!("Loaded {} buffered lines for influx", lines.len());
+        }
+        InfluxSink {
+            config: config.clone(),
+            client: client.clone(),
+            window: Mutex::default(),
+            pending: AtomicUsize::new(lines.len()),
+            buffer: Mutex::new((lines, None)),
+        }
+    }
+
+    /// write all buffered lines; they are kept for the next try unless they were written or rejected by influx
+    async fn write_buffer(&self, lines: &mut Vec<String>, since: &mut Option<Instant>) -> Result<()> {
+        let result = match self.post(lines.join("\n")).await {
+            Ok(()) => Ok(()),
+            Err(WriteError::Rejected(err)) => {
+                error!("Influx rejected {} lines, dropping them: {err}", lines.len());
+                Err(err)
+            }
+            Err(WriteError::Retryable(err)) => {
+                self.persist(lines).await;
+                return Err(err);
+            }
+        };
+        lines.clear();
+        *since = None;
+        self.persist(lines).await;
+        result
+    }
+
+    /// update the number of pending lines and the buffer file
+    async fn persist(&self, lines: &[String]) {
+        self.pending.store(lines.len(), Ordering::Relaxed);
+        let Some(path) = &self.config.influx_buffer_file else {
+            return;
+        };
+        let result = if lines.is_empty() {
+            match tokio::fs::remove_file(path).await {
+                Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+                result => result,
+            }
+        } else {
+            tokio::fs::write(path, format!("{}\n{}\n", buffer_header(&self.config), lines.join("\n"))).await
+        };
+        if let Err(err) = result {
+            error!("Could not write influx buffer file {path}: {err}");
+        }
+    }
+
+    /// add the point to the window; once a point of a later window arrives, the summarized lines of the
+    /// earlier windows are batched\
+    /// points only leave the window after their lines are in the buffer, which keeps them if the write fails
+    async fn aggregate(&self, point: &Point) -> Result<()> {
+        let secs = i64::try_from(self.config.influx_aggregate_window_secs)?;
+        let window_start = |p: &Point| p.time.unix_timestamp().div_euclid(secs) * secs;
+        let mut points = self.window.lock().await;
+        points.push(point.clone());
+        let mut result = Ok(());
+        while let Some(start) = points.first().map(window_start).filter(|start| *start < window_start(point)) {
+            let count = points.iter().take_while(|p| window_start(p) == start).count();
+            let line = aggregate_line(&self.config, &points[..count], start)?;
+            result = self.batch(line).await;
+            points.drain(..count);
+        }
+        result
+    }
+
+    /// add the line to the buffer and write the buffer, if the batch is full or old enough
+    async fn batch(&self, line: String) -> Result<()> {
+        let mut buffer = self.buffer.lock().await;
+        let (lines, since) = &mut *buffer;
+        lines.push(line);
+        since.get_or_insert_with(Instant::now);
+        if lines.len() > self.config.influx_buffer_max {
+            let dropped = lines.len() - self.config.influx_buffer_max;
+            lines.drain(..dropped);
+            warn!("Influx buffer is full, dropped {dropped} oldest lines");
+        }
+        let interval = self.config.influx_batch_interval_secs;
+        let due = lines.len() >= self.config.influx_batch_size
+            || (interval > 0 && since.is_some_and(|since| since.elapsed() >= Duration::from_secs(interval)));
+        if !due {
+            self.persist(lines).await;
+            return Ok(());
+        }
+        self.write_buffer(lines, since).await
+    }
+
+    /// send the lines to influx, retrying failed writes unless influx rejected them
+    async fn post(&self, body: String) -> Result<(), WriteError> {
+        let config = &self.config;
+        let body = if config.influx_gzip { gzip(&body).map_err(WriteError::Rejected)? } else { body.into_bytes() };
+        let mut result = Err(WriteError::Retryable(anyhow!("Influx retries should be at least 1")));
+        for attempt in 0..config.influx_retries {
+            if attempt > 0 {
+                // the delay doubles with every failed try
+                sleep(Duration::from_millis(config.influx_retry_delay_ms)
+                    .saturating_mul(2_u32.saturating_pow(attempt - 1))).await;
+            }
+            result = self.send(body.clone()).await;
+            if !matches!(result, Err(WriteError::Retryable(_))) {
+                break;
+            }
+        }
+        result
+    }
+
+    /// send the (possibly compressed) lines to influx once
+    async fn send(&self, body: Vec<u8>) -> Result<(), WriteError> {
+        let config = &self.config;
+        let request = authorize(config, self.client.post(write_url(config).map_err(WriteError::Rejected)?));
+        let request = if config.influx_gzip { request.header(CONTENT_ENCODING, "gzip") } else { request };
+        match request
+            .body(body)
+            .send()
+            .await {
+            Ok(v) => {
+                let status = v.status();
+                if status.is_success() {
+                    return Ok(());
+                }
+                error!("Influx success Error: {:?}", v);
+                let text = v.text().await.unwrap_or_default();
+                error!("Influx success Error: {:?}", text);
+                let err = anyhow!("Failed to put data into influx: {status}, {text}");
+                if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS {
+                    Err(WriteError::Rejected(err))
+                } else {
+                    Err(WriteError::Retryable(err))
+                }
+            }
+            Err(err) => {
+                // the url may contain the credentials of influx v1
+                let err = err.without_url();
+                error!("Influx response Error: {}", err);
+                Err(WriteError::Retryable(anyhow!(err)))
+            }
+        }
+    }
+}
+
+impl Sink for InfluxSink {
+    fn name(&self) -> &'static str {
+        "influx"
+    }
+
+    fn write<'a>(&'a self, point: &'a Point) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if self.config.influx_aggregate_window_secs == 0 {
+                return self.batch(point_line(&self.config, point)?).await;
+            }
+            self.aggregate(point).await
+        })
+    }
+
+    fn flush(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let mut buffer = self.buffer.lock().await;
+            let (lines, since) = &mut *buffer;
+            if lines.is_empty() {
+                return Ok(());
+            }
+            info!("Writing {} buffered lines to influx", lines.len());
+            self.write_buffer(lines, since).await
+        })
+    }
+
+    fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Read;
+    use std::sync::Arc;
+
+    use flate2::read::GzDecoder;
+    use serde_json::Value;
+
+    use crate::inverter::GridPhases;
+    use crate::sink::Precision;
+    use crate::tests::{mock_http, MockResponse};
+    use crate::wattpilot::CarState;
+
+    use super::*;
+
+    /// tags as given in the config
+    fn tags(tags: &[(&str, &str)]) -> Vec<(String, String)> {
+        tags.iter().map(|(key, value)| ((*key).to_owned(), (*value).to_owned())).collect()
+    }
+
+    #[test]
+    fn build_line_with_tags() -> Result<()> {
+        let line = build_line(
+            "solar",
+            &tags(&[("site", "home"), ("car_state_label", "Charging")]),
+            &SolarData::default(),
+            &WattpilotData::default(),
+            false,
+            1_700_000_000,
+        )?;
+        assert!(line.starts_with("solar,site=home,car_state_label=Charging old=0,new=0,both=0,battery_percentage=0,"));
+        assert!(line.ends_with(" 1700000000"));
+        assert!(line.contains(",wp_charging_values=\""));
+        Ok(())
+    }
+
+    #[test]
+    fn build_line_without_tags() -> Result<()> {
+        let line = build_line("solar", &[], &SolarData::default(), &WattpilotData::default(), false, 0)?;
+        assert!(line.starts_with("solar old=0,"));
+        Ok(())
+    }
+
+    #[test]
+    fn build_line_optional_fields() -> Result<()> {
+        let solar = SolarData {
+            old_inverter_power: None,
+            both_inverter_power: None,
+            new_inverter_power: 4200,
+            energy_day: Some(12_500),
+            battery_temperature: Some(21.5),
+            grid_phases: Some(GridPhases { u1: 230.1, u2: 231.0, u3: 229.5, i1: 1.5, i2: 2.0, i3: 0.5 }),
+            ..SolarData::default()
+        };
+        let wp = WattpilotData { amp: 16, current_delta: 2.5, car_limited: true, ..WattpilotData::default() };
+        let line = build_line("solar", &[], &solar, &wp, true, 0)?;
+        assert!(line.starts_with("solar new=4200,energy_day=12500,battery_temperature=21.5,battery_percentage=0,"));
+        assert!(!line.contains("old="));
+        assert!(!line.contains("both="));
+        assert!(!line.contains("energy_year="));
+        assert!(!line.contains("inverter_status_code="));
+        assert!(line.contains(",grid_u1=230.1,grid_u2=231,grid_u3=229.5,grid_i1=1.5,grid_i2=2,grid_i3=0.5,"));
+        assert!(line.contains(",wp_amp=16,wp_current_delta=2.5,wp_car_limited=true"));
+        assert!(!line.contains("wp_extra="));
+
+        let defaults = build_line("solar", &[], &SolarData::default(), &wp, false, 0)?;
+        assert!(!defaults.contains("grid_u1="));
+        assert!(!defaults.contains("wp_amp="));
+        Ok(())
+    }
+
+    #[test]
+    fn escape_every_special_character() {
+        // measurements escape comma and space
+        assert_eq!(escape_measurement("a,b"), r"a\,b");
+        assert_eq!(escape_measurement("a b"), r"a\ b");
+        assert_eq!(escape_measurement("a=b"), "a=b");
+        assert_eq!(escape_measurement(r#"a"b"#), r#"a"b"#);
+        assert_eq!(escape_measurement(r"a\b"), r"a\b");
+        // tags escape comma, equals sign and space
+        assert_eq!(escape_tag("a,b"), r"a\,b");
+        assert_eq!(escape_tag("a b"), r"a\ b");
+        assert_eq!(escape_tag("a=b"), r"a\=b");
+        assert_eq!(escape_tag(r#"a"b"#), r#"a"b"#);
+        assert_eq!(escape_tag(r"a\b"), r"a\b");
+        // string fields escape double quote and backslash
+        assert_eq!(escape_string("a,b"), "a,b");
+        assert_eq!(escape_string("a b"), "a b");
+        assert_eq!(escape_string("a=b"), "a=b");
+        assert_eq!(escape_string(r#"a"b"#), r#"a\"b"#);
+        assert_eq!(escape_string(r"a\b"), r"a\\b");
+        assert_eq!(escape_string(r#"\""#), r#"\\\""#);
+    }
+
+    /// read the string field `key` of a line like influx does: up to the first unescaped quote, unescaped;\
+    /// `None` if the field is missing or not followed by another field or the timestamp
+    fn string_field(line: &str, key: &str) -> Option<String> {
+        let start = line.find(&format!(",{key}=\"")).or_else(|| line.find(&format!(" {key}=\"")))? + key.len() + 3;
+        let mut value = String::new();
+        let mut chars = line[start..].chars();
+        loop {
+            match chars.next()? {
+                '\\' => value.push(chars.next().filter(|c| ['"', '\\'].contains(c))?),
+                '"' => return [Some(','), Some(' ')].contains(&chars.next()).then_some(value),
+                c => value.push(c),
+            }
+        }
+    }
+
+    #[test]
+    fn build_line_charging_values_with_quotes_and_backslashes() -> Result<()> {
+        let mut wp = WattpilotData {
+            tpcm: r#"["Type2 \"left\"", "C:\\charger"]"#.to_owned(),
+            ..WattpilotData::default()
+        };
+        wp.charging_values = serde_json::from_value(serde_json::json!(
+            [231.4, 230.9, 232.1, 0, 15.8, 15.6, 15.9, 3656.1, 3602, 3690.4, 0, 10948.5, 99, 99, 98, 0]
+        ))?;
+        let line = build_line("solar", &[], &SolarData::default(), &wp, false, 0)?;
+        let charging_values = string_field(&line, "wp_charging_values")
+            .ok_or_else(|| anyhow!("Invalid wp_charging_values in {line}"))?;
+        assert_eq!(charging_values, serde_json::to_string(&wp.charging_values)?);
+        assert!(charging_values.starts_with(r#"{"u1":231.4,"u2":230.9,"#));
+        assert_eq!(string_field(&line, "wp_tpcm").as_deref(), Some(wp.tpcm.as_str()));
+        assert!(line.contains(r#",wp_tpcm="[\"Type2 \\\"left\\\"\", \"C:\\\\charger\"]","#));
+        Ok(())
+    }
+
+    #[test]
+    fn build_line_escapes() -> Result<()> {
+        let wp = WattpilotData {
+            tpcm: r#"["a", "b\c"]"#.to_owned(),
+            car_state: CarState::Charging,
+            extra: HashMap::from([("fna".to_owned(), Value::String("my \"box\"".to_owned()))]),
+            ..WattpilotData::default()
+        };
+        let line = build_line(
+            "solar data,v2",
+            &tags(&[("location", "garage, left"), ("a=b", "c")]),
+            &SolarData::default(),
+            &wp,
+            false,
+            0,
+        )?;
+        assert!(line.starts_with(r"solar\ data\,v2,location=garage\,\ left,a\=b=c old=0,"));
+        assert!(line.contains(r#",wp_tpcm="[\"a\", \"b\\c\"]","#));
+        assert!(line.contains(r#",wp_extra="{\"fna\":\"my \\\"box\\\"\"}" "#));
+        assert!(line.contains(",wp_car_state=2,"));
+        Ok(())
+    }
+
+    #[test]
+    fn gzip_round_trip() -> Result<()> {
+        let lines = "solar old=0,new=4200 1700000000\nsolar old=0,new=4100 1700000010";
+        let mut decoded = String::new();
+        GzDecoder::new(gzip(lines)?.as_slice()).read_to_string(&mut decoded)?;
+        assert_eq!(decoded, lines);
+        Ok(())
+    }
+
+    /// authorization header of a request authorized for the config
+    fn authorization(config: &Config) -> Result<Option<String>> {
+        let request = authorize(config, reqwest::Client::new().post(write_url(config)?)).build()?;
+        Ok(request.headers().get(AUTHORIZATION).map(|value| value.to_str().map(str::to_owned)).transpose()?)
+    }
+
+    #[test]
+    fn endpoint_keeps_the_path_prefix() -> Result<()> {
+        for (url, query) in [
+            ("http://influx:8086", "http://influx:8086/api/v2/query"),
+            ("http://proxy/influx/", "http://proxy/influx/api/v2/query"),
+            ("http://proxy/influx/api/v2/write?org=o&bucket=b", "http://proxy/influx/api/v2/query"),
+        ] {
+            assert_eq!(endpoint(&Url::parse(url)?, "api/v2/query")?.as_str(), query);
+        }
+        assert_eq!(endpoint(&Url::parse("http://proxy/influx/write?db=solar")?, "query")?.as_str(), "http://proxy/influx/query");
+        let prefixed = Config {
+            influx_url: Some(Url::parse("https://host/influx")?),
+            influx_org: Some("org".to_owned()),
+            influx_bucket: Some("solar".to_owned()),
+            ..Config::default()
+        };
+        assert_eq!(write_url(&prefixed)?.as_str(), "https://host/influx/api/v2/write?org=org&bucket=solar&precision=s");
+        Ok(())
+    }
+
+    #[test]
+    fn write_url_and_authorization_v2() -> Result<()> {
+        let config = Config {
+            influx_url: Some(Url::parse("http://influx:8086")?),
+            influx_org: Some("my org".to_owned()),
+            influx_bucket: Some("solar".to_owned()),
+            influx_token: Some("abc".to_owned()),
+            ..Config::default()
+        };
+        assert_eq!(write_url(&config)?.as_str(), "http://influx:8086/api/v2/write?org=my+org&bucket=solar&precision=s");
+        assert_eq!(authorization(&config)?.as_deref(), Some("Token abc"));
+        Ok(())
+    }
+
+    #[test]
+    fn write_url_and_authorization_v1() -> Result<()> {
+        let config = Config {
+            influx_url: Some(Url::parse("http://influx:8086/write?db=solar")?),
+            influx_version: InfluxVersion::V1,
+            influx_token: Some("user:pass".to_owned()),
+            ..Config::default()
+        };
+        assert_eq!(write_url(&config)?.as_str(), "http://influx:8086/write?db=solar&precision=s");
+        // base64 of `user:pass`
+        assert_eq!(authorization(&config)?.as_deref(), Some("Basic dXNlcjpwYXNz"));
+        let anonymous = Config { influx_token: None, ..config.clone() };
+        assert_eq!(authorization(&anonymous)?, None);
+        let precision = Config { influx_url: Some(Url::parse("http://influx:8086/write?db=solar&precision=s")?), ..config };
+        assert_eq!(write_url(&precision)?.as_str(), "http://influx:8086/write?db=solar&precision=s");
+        Ok(())
+    }
+
+    #[test]
+    fn timestamp_matches_the_precision() -> Result<()> {
+        let point = Point {
+            time: OffsetDateTime::from_unix_timestamp_nanos(1_700_000_000_123_456_789)?,
+            ..point(0, Vec::new())?
+        };
+        for (precision, timestamp) in [
+            (Precision::S, "1700000000"),
+            (Precision::Ms, "1700000000123"),
+            (Precision::Us, "1700000000123456"),
+            (Precision::Ns, "1700000000123456789"),
+        ] {
+            let config = Config {
+                influx_measurement: Some("solar".to_owned()),
+                influx_precision: precision,
+                ..Config::default()
+            };
+            let line = point_line(&config, &point)?;
+            assert_eq!(line.rsplit_once(' ').map(|(_, time)| time), Some(timestamp));
+        }
+        Ok(())
+    }
+
+    /// point at the given unix time with the given wattpilots
+    fn point(secs: i64, wattpilots: Vec<(String, WattpilotData)>) -> Result<Point> {
+        Ok(Point {
+            time: OffsetDateTime::from_unix_timestamp(secs)?,
+            solar: SolarData { new_inverter_power: u32::try_from(secs)?, ..SolarData::default() },
+            wattpilot: wattpilots.first().map(|(_, data)| data.clone()).unwrap_or_default(),
+            wattpilots,
+        })
+    }
+
+    /// wattpilot charging with the given power
+    fn charging(id: &str, power: f32) -> (String, WattpilotData) {
+        let mut data = WattpilotData::default();
+        data.charging_values.pt = power;
+        (id.to_owned(), data)
+    }
+
+    #[test]
+    fn aggregate_line_with_several_wattpilots() -> Result<()> {
+        let config = Config {
+            influx_measurement: Some("solar".to_owned()),
+            influx_aggregations: "min, max".to_owned(),
+            ..Config::default()
+        };
+        let points = [
+            point(10, vec![charging("wp1", 1000.0), charging("wp2", 0.0)])?,
+            point(20, vec![charging("wp1", 3000.0), charging("wp2", 500.0)])?,
+        ];
+        let aggregated = aggregate_line(&config, &points, 0)?;
+        let lines: Vec<&str> = aggregated.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("solar old_min=0,old_max=0,new_min=10,new_max=20,"));
+        assert!(lines[0].ends_with(",samples=2i 0"));
+        assert!(!lines[0].contains("wp_"));
+        assert!(lines[1].starts_with("solar,wp_id=wp1 wp_wh_min=0,wp_wh_max=0,wp_power_min=1000,wp_power_max=3000,"));
+        assert!(lines[2].starts_with("solar,wp_id=wp2 wp_wh_min=0,wp_wh_max=0,wp_power_min=0,wp_power_max=500,"));
+        assert!(lines[2].ends_with(",samples=2i 0"));
+        assert!(!lines[2].contains("new_min"));
+        Ok(())
+    }
+
+    #[test]
+    fn aggregate_line_with_one_wattpilot() -> Result<()> {
+        let config = Config { influx_measurement: Some("solar".to_owned()), ..Config::default() };
+        let points = [point(10, vec![charging("wp1", 1000.0)])?, point(20, vec![charging("wp1", 3000.0)])?];
+        let line = aggregate_line(&config, &points, 0)?;
+        assert!(line.starts_with("solar old_mean=0,new_mean=15,"));
+        assert!(line.contains(",wp_power_mean=2000,"));
+        assert_eq!(line.lines().count(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn aggregate_keeps_the_window_until_it_is_buffered() -> Result<()> {
+        let config = Config {
+            // nothing listens on port 1, every write fails
+            influx_url: Some(Url::parse("http://127.0.0.1:1")?),
+            influx_measurement: Some("solar".to_owned()),
+            influx_retries: 1,
+            influx_aggregate_window_secs: 10,
+            ..Config::default()
+        };
+        let sink = InfluxSink::new(&config, &reqwest::Client::new());
+        sink.write(&point(20, vec![])?).await?;
+        sink.write(&point(25, vec![])?).await?;
+        assert_eq!(sink.pending(), 0);
+        assert!(sink.write(&point(31, vec![])?).await.is_err());
+        assert_eq!(sink.pending(), 1);
+        let buffer = sink.buffer.lock().await;
+        assert!(buffer.0[0].starts_with("solar old_mean=0,new_mean=22.5,"));
+        assert!(buffer.0[0].ends_with(",samples=2i 20"));
+        let window = sink.window.lock().await;
+        assert_eq!(window.len(), 1);
+        assert_eq!(window[0].time.unix_timestamp(), 31);
+        Ok(())
+    }
+
+    /// influx sink writing every point right away to the mock server, which answers with `status`;\
+    /// returns the sink and the number of requests the server got
+    async fn failing_sink(status: u16, buffer_file: Option<String>) -> Result<(InfluxSink, Arc<AtomicUsize>)> {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        let url = mock_http(Duration::ZERO, move |_, _| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            MockResponse::new(status, r#"{"code":"invalid"}"#)
+        }).await?;
+        let config = Config {
+            influx_url: Some(url),
+            influx_measurement: Some("solar".to_owned()),
+            influx_retry_delay_ms: 1,
+            influx_buffer_file: buffer_file,
+            ..Config::default()
+        };
+        Ok((InfluxSink::new(&config, &reqwest::Client::new()), requests))
+    }
+
+    #[tokio::test]
+    async fn rejected_batch_is_dropped() -> Result<()> {
+        let (sink, requests) = failing_sink(400, None).await?;
+        assert!(sink.write(&point(10, vec![])?).await.is_err());
+        assert_eq!(sink.pending(), 0);
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retryable_batch_is_kept() -> Result<()> {
+        for status in [429, 500, 503] {
+            let (sink, requests) = failing_sink(status, None).await?;
+            assert!(sink.write(&point(10, vec![])?).await.is_err());
+            assert_eq!(sink.pending(), 1);
+            assert_eq!(requests.load(Ordering::Relaxed), 3);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn buffer_file_keeps_its_precision() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("influx-buffer-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("buffer.txt");
+        let (sink, _) = failing_sink(503, Some(path.display().to_string())).await?;
+        assert!(sink.write(&point(10, vec![])?).await.is_err());
+        let content = fs::read_to_string(&path)?;
+        assert!(content.starts_with("# precision=s\nsolar old=0,new=10,"));
+
+        let config = |precision| Config {
+            influx_precision: precision,
+            influx_buffer_file: Some(path.display().to_string()),
+            ..Config::default()
+        };
+        let client = reqwest::Client::new();
+        assert_eq!(InfluxSink::new(&config(Precision::S), &client).pending(), 1);
+        assert_eq!(InfluxSink::new(&config(Precision::Ms), &client).pending(), 0);
+        // files without precision are from an older version
+        fs::write(&path, "solar old=0 10\n")?;
+        assert_eq!(InfluxSink::new(&config(Precision::S), &client).pending(), 0);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
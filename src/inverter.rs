@@ -1,55 +1,149 @@
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::ops::Add;
 use std::time::Duration;
-use anyhow::{anyhow};
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use futures_util::future::join_all;
+use crate::AppState;
 use crate::config::Config;
 use poem::{Error};
-use reqwest::{StatusCode};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
 use poem_openapi::{Object};
-use serde::{Deserialize};
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
 use time::OffsetDateTime;
-use tokio::sync::RwLock;
 use tokio::time::sleep;
-use tracing::{error, info};
+use tracing::{error, info, warn};
+use url::Url;
+use crate::digest::{authorization, InverterAuth};
+use crate::meter::{consumption_meter, ConsumptionMeter};
+use crate::plausibility::{check, Verdict};
+use crate::mock::MockInverter;
+use crate::solaredge::SolarEdgeInverter;
 use crate::utils::deserialize_null_default;
+use crate::wattpilot::Wattpilot;
 
 #[derive(Object, Debug, Clone)]
 pub struct SolarData {
     /// last time fronius was queried
     pub(crate) last_time: OffsetDateTime,
-    /// power produced by old pv system; data in watts
-    pub(crate) old_inverter_power: u32,
+    /// power produced by old pv system; data in watts\
+    /// not set if there is no secondary meter and `omit_missing_secondary_meter` is set
+    #[oai(skip_serializing_if_is_none)]
+    pub(crate) old_inverter_power: Option<u32>,
     /// power produced by new pv system; data in watts
     pub(crate) new_inverter_power: u32,
-    /// power produced by both pv systems; data in watts
-    pub(crate) both_inverter_power: u32,
+    /// power produced by both pv systems; data in watts\
+    /// not set if there is no secondary meter and `omit_missing_secondary_meter` is set
+    #[oai(skip_serializing_if_is_none)]
+    pub(crate) both_inverter_power: Option<u32>,
     /// current charge of the battery; data in percent
     pub(crate) battery_load_percentage: u8,
     /// current autonomy of the system; data in percent
     pub(crate) autonomy_percent: u8,
     /// current self consumption value; data in percent
     pub(crate) self_consumption_percent: u8,
+    /// share of the house consumption not covered by the grid, computed independent of the firmware;
+    /// 100 if nothing is consumed; data in percent
+    pub(crate) self_sufficiency_percent: u8,
     /// how much power is drained from battery; negative value means the battery is charging; data in watts
     pub(crate) drain_from_battery: i64,
     /// how much power is drained from grid; negative value means power is fed into the grid; data in watts
     pub(crate) drain_from_grid: i64,
     /// how much power the whole house is consuming; data in watts
     pub(crate) house_consumption: u64,
+    /// power imported from the grid, 0 while exporting; data in watts
+    pub(crate) grid_import_watts: u64,
+    /// power exported to the grid, 0 while importing; data in watts
+    pub(crate) grid_export_watts: u64,
+    /// share of the house consumption covered by both pv systems, capped at 100;
+    /// 100 if nothing is consumed; data in percent
+    pub(crate) solar_coverage_percent: u8,
+    /// how far the clock of the inverter is behind the clock of this server;
+    /// not set if the inverter does not report its time; data in seconds
+    #[oai(skip_serializing_if_is_none)]
+    pub(crate) inverter_clock_skew: Option<i64>,
+    /// power the wattpilot is charging the car with; 0 if there is no wattpilot;
+    /// not set if `report_ev_charging_power` is not set; data in watts
+    #[oai(skip_serializing_if_is_none)]
+    pub(crate) ev_charging_power: Option<u32>,
+    /// voltages and currents of the grid phases, measured by the smart meter;
+    /// not set if `fetch_meter` is not set or the meter could not be fetched
+    #[oai(skip_serializing_if_is_none)]
+    pub(crate) grid_phases: Option<GridPhases>,
+    /// energy produced today; not set if the inverter does not report it; data in Wh
+    #[oai(skip_serializing_if_is_none)]
+    pub(crate) energy_day: Option<u64>,
+    /// energy produced this year; not set if the inverter does not report it; data in Wh
+    #[oai(skip_serializing_if_is_none)]
+    pub(crate) energy_year: Option<u64>,
+    /// energy produced over the lifetime of the inverter; not set if the inverter does not report it; data in Wh
+    #[oai(skip_serializing_if_is_none)]
+    pub(crate) energy_total: Option<u64>,
+    /// temperature of the battery cells; not set if `fetch_inverter_details` is not set or there is no battery;
+    /// data in degrees celsius
+    #[oai(skip_serializing_if_is_none)]
+    pub(crate) battery_temperature: Option<f64>,
+    /// status code of the inverter, e.g. 7 = running; not set if `fetch_inverter_details` is not set
+    #[oai(skip_serializing_if_is_none)]
+    pub(crate) inverter_status_code: Option<i64>,
+    /// error code of the inverter, 0 = no error; not set if `fetch_inverter_details` is not set
+    #[oai(skip_serializing_if_is_none)]
+    pub(crate) inverter_error_code: Option<i64>,
+}
+
+#[derive(Object, Debug, Clone, Default)]
+pub struct GridPhases {
+    /// voltage of phase 1; data in volts
+    pub(crate) u1: f64,
+    /// voltage of phase 2; data in volts
+    pub(crate) u2: f64,
+    /// voltage of phase 3; data in volts
+    pub(crate) u3: f64,
+    /// current of phase 1; data in ampere
+    pub(crate) i1: f64,
+    /// current of phase 2; data in ampere
+    pub(crate) i2: f64,
+    /// current of phase 3; data in ampere
+    pub(crate) i3: f64,
+}
+
+impl SolarData {
+    /// whether the data is recent enough to be used; data is stale once two polls and a timeout have passed\
+    /// without an update, so a single failed poll does not mark it as stale
+    pub(crate) fn is_fresh(&self, config: &Config) -> bool {
+        let max_age = 2 * config.poll_interval_secs + config.inverter_timeout_secs;
+        OffsetDateTime::now_utc() - self.last_time <= Duration::from_secs(max_age)
+    }
 }
 
 impl Default for SolarData {
     fn default() -> Self {
         SolarData {
             last_time: OffsetDateTime::UNIX_EPOCH,
-            old_inverter_power: Default::default(),
+            old_inverter_power: Some(0),
             new_inverter_power: Default::default(),
-            both_inverter_power: Default::default(),
+            both_inverter_power: Some(0),
             battery_load_percentage: Default::default(),
             autonomy_percent: Default::default(),
             self_consumption_percent: Default::default(),
+            self_sufficiency_percent: Default::default(),
             drain_from_battery: Default::default(),
             drain_from_grid: Default::default(),
             house_consumption: Default::default(),
+            grid_import_watts: Default::default(),
+            grid_export_watts: Default::default(),
+            solar_coverage_percent: Default::default(),
+            inverter_clock_skew: None,
+            ev_charging_power: None,
+            grid_phases: None,
+            energy_day: None,
+            energy_year: None,
+            energy_total: None,
+            battery_temperature: None,
+            inverter_status_code: None,
+            inverter_error_code: None,
         }
     }
 }
@@ -62,8 +156,47 @@ struct SecondaryMeter {
     power: f64,
 }
 
+/// which kind of inverter is queried
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum InverterKind {
+    /// fronius solar api (`/status/powerflow`)
+    Fronius,
+    /// solaredge monitoring api
+    SolarEdge,
+    /// simulated values, no inverter needed
+    Mock,
+}
+
+/// http method used to query the inverter
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub(crate) enum InverterMethod {
+    #[serde(alias = "get")]
+    Get,
+    /// sends `inverter_request_body` as json
+    #[serde(alias = "post")]
+    Post,
+}
+
+impl InverterMethod {
+    /// name of this method, as sent to the inverter
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            InverterMethod::Get => "GET",
+            InverterMethod::Post => "POST",
+        }
+    }
+}
+
+/// an inverter which delivers the solar values
+pub(crate) trait Inverter {
+    /// fetch the current values of the system, together with the raw response of the inverter
+    async fn fetch(&self, config: &Config, client: &reqwest::Client, url: &Url) -> anyhow::Result<(SolarData, String)>;
+}
+
 #[derive(Deserialize, Debug)]
-struct Inverter {
+struct InverterValues {
     /// current charge of the battery; data in percent
     #[serde(alias = "SOC", default, deserialize_with = "deserialize_null_default")]
     battery_percent: f64,
@@ -89,88 +222,816 @@ struct Site {
     /// current self consumption value; data in percent
     #[serde(alias = "rel_SelfConsumption", deserialize_with = "deserialize_null_default")]
     self_consumption: f64,
+    /// energy produced today; data in Wh
+    #[serde(alias = "E_Day", default)]
+    energy_day: Option<f64>,
+    /// energy produced this year; data in Wh
+    #[serde(alias = "E_Year", default)]
+    energy_year: Option<f64>,
+    /// energy produced over the lifetime of the inverter; data in Wh
+    #[serde(alias = "E_Total", default)]
+    energy_total: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MeterValues {
+    /// where the meter is installed; 0 = grid feed-in point
+    #[serde(alias = "Meter_Location_Current", default, deserialize_with = "deserialize_null_default")]
+    location: f64,
+    #[serde(alias = "Voltage_AC_Phase_1", default, deserialize_with = "deserialize_null_default")]
+    voltage_1: f64,
+    #[serde(alias = "Voltage_AC_Phase_2", default, deserialize_with = "deserialize_null_default")]
+    voltage_2: f64,
+    #[serde(alias = "Voltage_AC_Phase_3", default, deserialize_with = "deserialize_null_default")]
+    voltage_3: f64,
+    #[serde(alias = "Current_AC_Phase_1", default, deserialize_with = "deserialize_null_default")]
+    current_1: f64,
+    #[serde(alias = "Current_AC_Phase_2", default, deserialize_with = "deserialize_null_default")]
+    current_2: f64,
+    #[serde(alias = "Current_AC_Phase_3", default, deserialize_with = "deserialize_null_default")]
+    current_3: f64,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct StorageController {
+    /// temperature of the battery cells; data in degrees celsius
+    #[serde(alias = "Temperature_Cell", default, deserialize_with = "deserialize_null_default")]
+    temperature: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Storage {
+    #[serde(alias = "Controller", default)]
+    controller: StorageController,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct DeviceStatus {
+    /// state of the inverter, e.g. 7 = running
+    #[serde(alias = "StatusCode", default, deserialize_with = "deserialize_null_default")]
+    status_code: i64,
+    /// error of the inverter; 0 = no error
+    #[serde(alias = "ErrorCode", default, deserialize_with = "deserialize_null_default")]
+    error_code: i64,
+}
+
+#[derive(Deserialize, Debug)]
+struct CommonInverterData {
+    #[serde(alias = "DeviceStatus", default)]
+    device_status: DeviceStatus,
+}
+
+#[derive(Deserialize, Debug)]
+struct SolarApiBody<T> {
+    #[serde(alias = "Data")]
+    data: T,
+}
+
+/// response of the fronius solar api (`/solar_api/v1/...`)
+#[derive(Deserialize, Debug)]
+struct SolarApiJson<T> {
+    #[serde(alias = "Body")]
+    body: SolarApiBody<T>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct HeadStatus {
+    /// 0 = ok, everything else is an error
+    #[serde(alias = "Code", default, deserialize_with = "deserialize_null_default")]
+    code: i64,
+    /// why the request failed
+    #[serde(alias = "Reason", default, deserialize_with = "deserialize_null_default")]
+    reason: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Head {
+    /// current time of the inverter; RFC 3339
+    #[serde(alias = "Timestamp", default)]
+    timestamp: Option<String>,
+    /// status of the request; not reported by every firmware
+    #[serde(alias = "Status", default)]
+    status: HeadStatus,
+}
+
+/// current time of the inverter, as reported by the `/status/powerflow` of GEN24 firmwares; local time
+#[derive(Deserialize, Debug, Default)]
+struct Common {
+    /// e.g. `16.10.2026`
+    #[serde(default)]
+    datestamp: Option<String>,
+    /// e.g. `12:00:00`
+    #[serde(default)]
+    timestamp: Option<String>,
+}
+
+/// only the head of a response, which is also sent with errors
+#[derive(Deserialize, Debug)]
+struct HeadJson {
+    #[serde(alias = "Head", default)]
+    head: Head,
 }
 
 #[derive(Deserialize, Debug)]
 struct SolarJson {
-    #[serde(alias = "SecondaryMeters")]
+    #[serde(alias = "Head", default)]
+    head: Head,
+    #[serde(alias = "Common", default)]
+    common: Common,
+    #[serde(alias = "secondaryMeters", alias = "SecondaryMeters", default)]
     secondary_meters: HashMap<String, SecondaryMeter>,
     #[serde(alias = "inverters")]
-    inverters: Vec<Inverter>,
-    #[serde(alias = "inverters")]
+    inverters: Vec<InverterValues>,
+    #[serde(alias = "site")]
     site: Site,
 }
 
-async fn get_data(config: &Config) -> anyhow::Result<SolarData> {
+/// compute self sufficiency from house consumption and grid power (positive = import); data in percent
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub(crate) fn self_sufficiency(load: f64, grid: f64) -> u8 {
+    if load <= 0.0 {
+        return 100;
+    }
+    ((load - grid.max(0.0)) / load * 100.0).clamp(0.0, 100.0).round() as u8
+}
+
+/// compute the share of the house consumption covered by pv production; data in percent
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub(crate) fn solar_coverage(load: f64, pv: f64) -> u8 {
+    if load <= 0.0 {
+        return 100;
+    }
+    (pv.max(0.0) / load * 100.0).clamp(0.0, 100.0).round() as u8
+}
+
+/// reconstruct the house consumption from pv, grid and battery power, if the inverter does not report it;
+/// data in watts
+fn reconstruct_consumption(site: &Site) -> f64 {
+    // grid: positive = import, battery: positive = discharge
+    (site.power_pv + site.power_grid + site.power_battery).max(0.0)
+}
+
+/// current time of the inverter, from `Head.Timestamp` (RFC 3339) or else from `common`,
+/// whose local time is read in the configured `timezone`
+fn inverter_time(config: &Config, head: &Head, common: &Common) -> Option<DateTime<Utc>> {
+    if let Some(timestamp) = &head.timestamp {
+        return match DateTime::parse_from_rfc3339(timestamp) {
+            Ok(time) => Some(time.with_timezone(&Utc)),
+            Err(err) => {
+                warn!("Could not parse inverter timestamp {timestamp}: {err}");
+                None
+            }
+        };
+    }
+    let (Some(date), Some(time)) = (&common.datestamp, &common.timestamp) else {
+        return None;
+    };
+    match NaiveDateTime::parse_from_str(&format!("{date} {time}"), "%d.%m.%Y %H:%M:%S") {
+        Ok(local) => config.timezone.from_local_datetime(&local).earliest().map(|zoned| zoned.with_timezone(&Utc)),
+        Err(err) => {
+            warn!("Could not parse inverter time {date} {time}: {err}");
+            None
+        }
+    }
+}
+
+/// compute how far the clock of the inverter is behind the clock of this server; data in seconds
+fn clock_skew(config: &Config, head: &Head, common: &Common) -> Option<i64> {
+    let skew = (Utc::now() - inverter_time(config, head, common)?).num_seconds();
+    if skew.unsigned_abs() > config.inverter_clock_skew_warn_secs {
+        warn!("Inverter clock is off by {skew}s");
+    }
+    Some(skew)
+}
+
+/// send a request to the inverter like the powerflow request, answering a digest challenge once
+async fn send(config: &Config, client: &reqwest::Client, url: &Url) -> anyhow::Result<Response> {
+    let resp = request(config, client, url, None).send().await?;
+    if resp.status() != StatusCode::UNAUTHORIZED || config.inverter_auth != InverterAuth::Digest {
+        return Ok(resp);
+    }
+    let challenge = resp.headers().get(WWW_AUTHENTICATE).and_then(|h| h.to_str().ok()).unwrap_or_default();
+    let digest = authorization(
+        challenge,
+        config.inverter_method.name(),
+        url,
+        config.inverter_username.as_deref().unwrap_or_default(),
+        config.inverter_password.as_deref().unwrap_or_default(),
+    )?;
+    Ok(request(config, client, url, Some(&digest)).send().await?)
+}
+
+/// query an endpoint of the fronius solar api, e.g. `GetMeterRealtimeData.cgi?Scope=System`
+async fn solar_api<T: DeserializeOwned>(config: &Config, client: &reqwest::Client, url: &Url, endpoint: &str) -> anyhow::Result<T> {
+    let resp = send(config, client, &url.join(&format!("/solar_api/v1/{endpoint}"))?).await?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("Response Error: {}, {}", resp.status(), resp.text().await?));
+    }
+    Ok(serde_json::from_str::<SolarApiJson<T>>(resp.text().await?.as_str())?.body.data)
+}
+
+/// parse the comma separated list of inverter device ids; one id, or one per inverter url
+pub(crate) fn inverter_device_ids(config: &Config) -> anyhow::Result<Vec<u32>> {
+    let ids = config.inverter_device_ids.split(',').map(str::trim).filter(|s| !s.is_empty())
+        .map(|id| id.parse::<u32>().map_err(|err| anyhow!("Invalid inverter device id {id}: {err}")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let urls = inverter_urls(config)?.len();
+    if ids.is_empty() || (ids.len() > 1 && ids.len() != urls) {
+        bail!("Got {urls} inverter urls, but {} inverter device ids", ids.len());
+    }
+    Ok(ids)
+}
+
+/// id of the inverter at this url in the solar api
+fn device_id(config: &Config, url: &Url) -> anyhow::Result<u32> {
+    let ids = inverter_device_ids(config)?;
+    let index = inverter_urls(config)?.iter().position(|u| u == url).unwrap_or_default();
+    ids.get(index).or(ids.first()).copied().ok_or_else(|| anyhow!("No inverter device id"))
+}
+
+/// fetch the temperature of the battery and the status of the inverter;\
+/// the temperature is not set if there is no battery
+async fn fetch_inverter_details(
+    config: &Config,
+    client: &reqwest::Client,
+    url: &Url,
+) -> anyhow::Result<(Option<f64>, DeviceStatus)> {
+    let storages: HashMap<String, Storage> = solar_api(config, client, url, "GetStorageRealtimeData.cgi?Scope=System")
+        .await
+        .unwrap_or_default();
+    let temperature = storages.values().map(|storage| storage.controller.temperature).reduce(f64::max);
+    let inverter: CommonInverterData = solar_api(
+        config,
+        client,
+        url,
+        &format!(
+            "GetInverterRealtimeData.cgi?Scope=Device&DeviceId={}&DataCollection=CommonInverterData",
+            device_id(config, url)?,
+        ),
+    ).await?;
+    Ok((temperature, inverter.device_status))
+}
+
+/// fetch the phase values of the smart meter at the grid feed-in point, or of the first meter
+async fn fetch_grid_phases(config: &Config, client: &reqwest::Client, url: &Url) -> anyhow::Result<GridPhases> {
+    let data: HashMap<String, MeterValues> =
+        solar_api(config, client, url, "GetMeterRealtimeData.cgi?Scope=System").await?;
+    let mut meters: Vec<(String, MeterValues)> = data.into_iter().collect();
+    meters.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let meter = meters.iter().find(|(_, meter)| meter.location == 0.0).or(meters.first())
+        .map(|(_, meter)| meter)
+        .ok_or_else(|| anyhow!("Inverter reports no meter"))?;
+    Ok(GridPhases {
+        u1: meter.voltage_1,
+        u2: meter.voltage_2,
+        u3: meter.voltage_3,
+        i1: meter.current_1,
+        i2: meter.current_2,
+        i3: meter.current_3,
+    })
+}
+
+/// build the request to the inverter, with the configured method, body and timeout
+fn request(config: &Config, client: &reqwest::Client, url: &Url, digest: Option<&str>) -> RequestBuilder {
+    let mut request = match config.inverter_method {
+        InverterMethod::Post => {
+            let request = client.post(url.clone()).header(CONTENT_TYPE, "application/json");
+            match &config.inverter_request_body {
+                None => request,
+                Some(body) => request.body(body.clone()),
+            }
+        }
+        InverterMethod::Get => client.get(url.clone()),
+    };
+    if let Some(header) = digest {
+        request = request.header(AUTHORIZATION, header);
+    }
+    request.timeout(Duration::from_secs(config.inverter_timeout_secs))
+}
+
+/// query `/status/powerflow`, retrying on errors; returns the parsed and the raw response
+async fn fetch_powerflow(config: &Config, client: &reqwest::Client, url: &Url) -> anyhow::Result<(SolarJson, String)> {
     let mut json_opt: Option<SolarJson> = None;
+    let mut raw = String::new();
     let mut error: Option<String> = None;
     let mut success: bool = false;
-    let sleep_time = Duration::from_millis(100);
-    for _ in 0..3 {
-        let client = reqwest::Client::new();
-        let resp = client
-            .get(config.inverter_url.clone().unwrap().join("/status/powerflow")?)
-            .send()
-            .await?;
+    // answer to the last digest challenge of the inverter
+    let mut digest: Option<String> = None;
+    for attempt in 0..config.inverter_retries {
+        // the delay doubles with every failed try
+        let sleep_time = Duration::from_millis(config.inverter_retry_delay_ms)
+            .saturating_mul(2_u32.saturating_pow(attempt));
+        let url = url.join("/status/powerflow")?;
+        let resp = match request(config, client, &url, digest.as_deref()).send().await {
+            Ok(v) => v,
+            Err(err) => {
+                error = Some(format!("Request Error: {err}"));
+                sleep(sleep_time).await;
+                continue;
+            }
+        };
+        if resp.status() == StatusCode::UNAUTHORIZED && config.inverter_auth == InverterAuth::Digest {
+            // the challenge is answered with the next try
+            let challenge = resp.headers().get(WWW_AUTHENTICATE).and_then(|h| h.to_str().ok()).unwrap_or_default();
+            digest = Some(authorization(
+                challenge,
+                config.inverter_method.name(),
+                &url,
+                config.inverter_username.as_deref().unwrap_or_default(),
+                config.inverter_password.as_deref().unwrap_or_default(),
+            )?);
+            error = Some(format!("Inverter requested authentication: {challenge}"));
+            continue;
+        }
         if !resp.status().is_success() {
             error = Some(format!("Response Error: {}, {}", resp.status(), resp.text().await?));
             sleep(sleep_time).await;
             continue;
         }
         let text = resp.text().await?;
+        // error responses come without the values, so the status is checked first
+        let head = serde_json::from_str::<HeadJson>(text.as_str()).map(|json| json.head).unwrap_or_default();
+        if head.status.code != 0 {
+            error = Some(format!("Inverter Error: {}, {}", head.status.code, head.status.reason));
+            sleep(sleep_time).await;
+            continue;
+        }
         match serde_json::from_str::<SolarJson>(text.as_str()) {
             Ok(v) => {
                 success = true;
                 json_opt = Some(v);
+                raw = text;
                 break;
             }
             Err(err) => {
-                error = Some(format!("Json Error: {}, {}", err, text));
+                error = Some(format!("Json Error: {err}, {text}"));
                 sleep(sleep_time).await;
-                continue;
             }
-        };
+        }
     }
     if !success {
-        error!("{}", error.unwrap_or_default());
-        return Err(anyhow!(Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)));
+        let error = error.unwrap_or_default();
+        error!("{error}");
+        return Err(anyhow!(error));
     }
 
     let Some(json) = json_opt else {
         error!("json_opt is empty");
         return Err(anyhow!(Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)));
     };
-    let secondary_value = json.secondary_meters.values().last().unwrap_or(&SecondaryMeter {
-        power: 0.0,
-    });
-    let inverter = json.inverters.last().unwrap_or(&Inverter {
-        battery_percent: 0.0,
-    });
-    Ok(SolarData {
+    Ok((json, raw))
+}
+
+/// fronius inverter, queried via `/status/powerflow`
+pub(crate) struct FroniusInverter;
+
+impl Inverter for FroniusInverter {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    async fn fetch(&self, config: &Config, client: &reqwest::Client, url: &Url) -> anyhow::Result<(SolarData, String)> {
+        let (json, raw) = fetch_powerflow(config, client, url).await?;
+        let omit_secondary = json.secondary_meters.is_empty() && config.omit_missing_secondary_meter;
+        let secondary_value = json.secondary_meters.values().last().unwrap_or(&SecondaryMeter {
+            power: 0.0,
+        });
+        let inverter = json.inverters.last().unwrap_or(&InverterValues {
+            battery_percent: 0.0,
+        });
+        let mut house_consumption = -json.site.house_consumption;
+        if config.house_consumption_fallback && house_consumption == 0.0
+            && (json.site.power_pv != 0.0 || json.site.power_grid != 0.0) {
+            house_consumption = reconstruct_consumption(&json.site);
+        }
+        let grid_phases = if config.fetch_meter {
+            match fetch_grid_phases(config, client, url).await {
+                Ok(v) => Some(v),
+                Err(err) => {
+                    error!("Could not fetch meter: {:?}", err);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let (battery_temperature, device_status) = if config.fetch_inverter_details {
+            match fetch_inverter_details(config, client, url).await {
+                Ok((temperature, status)) => (temperature, Some(status)),
+                Err(err) => {
+                    error!("Could not fetch inverter details: {:?}", err);
+                    (None, None)
+                }
+            }
+        } else {
+            (None, None)
+        };
+        Ok((SolarData {
+            last_time: OffsetDateTime::now_utc(),
+            old_inverter_power: (!omit_secondary).then_some(secondary_value.power as u32),
+            new_inverter_power: json.site.power_pv as u32,
+            both_inverter_power: (!omit_secondary).then_some((secondary_value.power + json.site.power_pv) as u32),
+            battery_load_percentage: inverter.battery_percent as u8,
+            autonomy_percent: json.site.autonomy as u8,
+            self_consumption_percent: json.site.self_consumption as u8,
+            self_sufficiency_percent: self_sufficiency(house_consumption, json.site.power_grid),
+            drain_from_battery: json.site.power_battery as i64,
+            drain_from_grid: json.site.power_grid as i64,
+            house_consumption: house_consumption as u64,
+            grid_import_watts: json.site.power_grid.max(0.0) as u64,
+            grid_export_watts: (-json.site.power_grid).max(0.0) as u64,
+            solar_coverage_percent: solar_coverage(house_consumption, secondary_value.power + json.site.power_pv),
+            inverter_clock_skew: clock_skew(config, &json.head, &json.common),
+            ev_charging_power: None,
+            grid_phases,
+            energy_day: json.site.energy_day.map(|energy| energy as u64),
+            energy_year: json.site.energy_year.map(|energy| energy as u64),
+            energy_total: json.site.energy_total.map(|energy| energy as u64),
+            battery_temperature,
+            inverter_status_code: device_status.as_ref().map(|status| status.status_code),
+            inverter_error_code: device_status.as_ref().map(|status| status.error_code),
+        }, raw))
+    }
+}
+
+/// parse the comma separated list of inverter urls
+pub(crate) fn inverter_urls(config: &Config) -> anyhow::Result<Vec<Url>> {
+    Ok(config.inverter_url.split(',').map(str::trim).filter(|s| !s.is_empty())
+        .map(Url::parse)
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// sum up the values which are set; `None` if no value is set
+fn sum_option<T: Default + Add<Output = T>>(values: Vec<Option<T>>) -> Option<T> {
+    values.into_iter().reduce(|a, b| match (a, b) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or_default() + b.unwrap_or_default()),
+    }).flatten()
+}
+
+/// combine the values of several inverters into one;\
+/// powers are summed up, percentages reported by the inverters are averaged
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+fn combine(mut datas: Vec<SolarData>) -> Option<SolarData> {
+    if datas.len() <= 1 {
+        return datas.pop();
+    }
+    let count = datas.len() as u64;
+    let average = |values: Vec<u8>| (values.into_iter().map(u64::from).sum::<u64>() / count) as u8;
+    let house_consumption: u64 = datas.iter().map(|d| d.house_consumption).sum();
+    let drain_from_grid: i64 = datas.iter().map(|d| d.drain_from_grid).sum();
+    let new_inverter_power: u32 = datas.iter().map(|d| d.new_inverter_power).sum();
+    let old_inverter_power = sum_option(datas.iter().map(|d| d.old_inverter_power).collect());
+    Some(SolarData {
         last_time: OffsetDateTime::now_utc(),
-        old_inverter_power: secondary_value.power as u32,
-        new_inverter_power: json.site.power_pv as u32,
-        both_inverter_power: (secondary_value.power + json.site.power_pv) as u32,
-        battery_load_percentage: inverter.battery_percent as u8,
-        autonomy_percent: json.site.autonomy as u8,
-        self_consumption_percent: json.site.self_consumption as u8,
-        drain_from_battery: json.site.power_battery as i64,
-        drain_from_grid: json.site.power_grid as i64,
-        house_consumption: (-1.0 * json.site.house_consumption) as u64,
+        old_inverter_power,
+        new_inverter_power,
+        both_inverter_power: sum_option(datas.iter().map(|d| d.both_inverter_power).collect()),
+        battery_load_percentage: average(datas.iter().map(|d| d.battery_load_percentage).collect()),
+        autonomy_percent: average(datas.iter().map(|d| d.autonomy_percent).collect()),
+        self_consumption_percent: average(datas.iter().map(|d| d.self_consumption_percent).collect()),
+        self_sufficiency_percent: self_sufficiency(house_consumption as f64, drain_from_grid as f64),
+        drain_from_battery: datas.iter().map(|d| d.drain_from_battery).sum(),
+        drain_from_grid,
+        house_consumption,
+        grid_import_watts: drain_from_grid.max(0).unsigned_abs(),
+        grid_export_watts: drain_from_grid.min(0).unsigned_abs(),
+        solar_coverage_percent: solar_coverage(
+            house_consumption as f64,
+            f64::from(old_inverter_power.unwrap_or_default() + new_inverter_power),
+        ),
+        inverter_clock_skew: datas.iter().filter_map(|d| d.inverter_clock_skew).max_by_key(|skew| skew.unsigned_abs()),
+        ev_charging_power: None,
+        grid_phases: datas.iter().find_map(|d| d.grid_phases.clone()),
+        energy_day: sum_option(datas.iter().map(|d| d.energy_day).collect()),
+        energy_year: sum_option(datas.iter().map(|d| d.energy_year).collect()),
+        energy_total: sum_option(datas.iter().map(|d| d.energy_total).collect()),
+        battery_temperature: datas.iter().filter_map(|d| d.battery_temperature).reduce(f64::max),
+        inverter_status_code: datas.iter().find_map(|d| d.inverter_status_code),
+        // report the error of any inverter
+        inverter_error_code: datas.iter().filter_map(|d| d.inverter_error_code).find(|code| *code != 0)
+            .or_else(|| datas.iter().find_map(|d| d.inverter_error_code)),
     })
 }
 
-
-pub(crate) async fn fetch_solar_values(config: &Config, solar_data: Arc<RwLock<SolarData>>) -> bool {
-    info!("Fetching data from Fronius at {}", OffsetDateTime::now_utc());
-    match get_data(config).await {
-        Ok(v) => {
-            *solar_data.write().await = v;
-            true
+/// fetch the current values from all configured inverters, together with their raw responses;\
+/// if one inverter fails, the whole fetch fails, partial sums would look like a drop of the production
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+async fn get_data(
+    config: &Config,
+    client: &reqwest::Client,
+    urls: &[Url],
+    mock: &MockInverter,
+) -> anyhow::Result<(SolarData, Vec<String>)> {
+    let results = join_all(urls.iter().map(|url| async move {
+        match config.inverter_kind {
+            InverterKind::Fronius => FroniusInverter.fetch(config, client, url).await,
+            InverterKind::SolarEdge => SolarEdgeInverter.fetch(config, client, url).await,
+            InverterKind::Mock => mock.fetch(config, client, url).await,
         }
+    })).await;
+    let (datas, raw): (Vec<SolarData>, Vec<String>) = results.into_iter()
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .into_iter()
+        .unzip();
+    let mut data = combine(datas).ok_or_else(|| anyhow!("No inverter configured"))?;
+    if let Some(meter) = consumption_meter(config) {
+        match meter.consumption(client).await {
+            Ok(house_consumption) => {
+                data.house_consumption = house_consumption as u64;
+                data.self_sufficiency_percent = self_sufficiency(house_consumption, data.drain_from_grid as f64);
+                data.solar_coverage_percent = solar_coverage(
+                    house_consumption,
+                    f64::from(data.both_inverter_power.unwrap_or(data.new_inverter_power)),
+                );
+            }
+            Err(err) => error!("Could not fetch consumption meter, using inverter value: {:?}", err),
+        }
+    }
+    Ok((data, raw))
+}
+
+
+/// fetch new values and store them, unless they are dropped as implausible; the raw responses are always stored
+pub(crate) async fn fetch_solar_values(state: &AppState) -> anyhow::Result<Verdict> {
+    let config = &state.config;
+    info!("Fetching data from inverter at {}", OffsetDateTime::now_utc());
+    let (mut data, raw) = match get_data(config, &state.inverter_client, &state.inverter_urls, &state.mock_inverter).await {
+        Ok(v) => v,
         Err(err) => {
             error!("{:?}", err);
-            false
+            return Err(err);
         }
+    };
+    *state.inverter_raw.write().await = raw;
+    if config.report_ev_charging_power {
+        data.ev_charging_power = Some(Wattpilot::charging_power(&state.wattpilots, config).await);
+    }
+    let mut lock = state.solar_data.write().await;
+    let verdict = check(&state.plausibility_bounds, config.plausibility_action, &mut data, &lock);
+    if matches!(verdict, Verdict::Accepted(_)) {
+        *lock = data;
+    }
+    Ok(verdict)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use reqwest::Method;
+
+    use super::*;
+    use crate::tests::{mock_http, MockResponse};
+
+    #[test]
+    fn post_request_sends_configured_body() -> anyhow::Result<()> {
+        let config = Config {
+            inverter_method: InverterMethod::Post,
+            inverter_request_body: Some(r#"{"query":"powerflow"}"#.to_owned()),
+            inverter_timeout_secs: 3,
+            ..Config::default()
+        };
+        let url = Url::parse("http://inverter/status/powerflow")?;
+        let request = request(&config, &reqwest::Client::new(), &url, Some("Digest username=\"admin\""))
+            .build()?;
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(request.url(), &url);
+        assert_eq!(request.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(request.headers()[AUTHORIZATION], "Digest username=\"admin\"");
+        assert_eq!(request.body().and_then(reqwest::Body::as_bytes), Some(br#"{"query":"powerflow"}"#.as_slice()));
+        assert_eq!(request.timeout(), Some(&Duration::from_secs(3)));
+        Ok(())
+    }
+
+    #[test]
+    fn inverter_method_from_config() -> anyhow::Result<()> {
+        assert_eq!(serde_json::from_str::<InverterMethod>(r#""POST""#)?, InverterMethod::Post);
+        assert_eq!(serde_json::from_str::<InverterMethod>(r#""get""#)?, InverterMethod::Get);
+        assert!(serde_json::from_str::<InverterMethod>(r#""PUT""#).is_err());
+        Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn post_request_without_body() -> anyhow::Result<()> {
+        let config = Config { inverter_method: InverterMethod::Post, ..Config::default() };
+        let request = request(&config, &reqwest::Client::new(), &Url::parse("http://inverter")?, None).build()?;
+        assert_eq!(request.method(), Method::POST);
+        assert!(request.body().is_none());
+        assert!(request.headers().get(AUTHORIZATION).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn get_request_ignores_body() -> anyhow::Result<()> {
+        let config = Config { inverter_request_body: Some("{}".to_owned()), ..Config::default() };
+        let request = request(&config, &reqwest::Client::new(), &Url::parse("http://inverter")?, None).build()?;
+        assert_eq!(request.method(), Method::GET);
+        assert!(request.body().is_none());
+        assert!(request.headers().get(CONTENT_TYPE).is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn slow_inverter_times_out() -> anyhow::Result<()> {
+        let url = mock_http(Duration::from_secs(30), |_, _| MockResponse::new(200, "")).await?;
+        let config = Config { inverter_timeout_secs: 1, inverter_retries: 2, inverter_retry_delay_ms: 10, ..Config::default() };
+        let started = Instant::now();
+        let result = fetch_powerflow(&config, &reqwest::Client::new(), &url).await;
+        assert!(result.is_err_and(|err| err.to_string().starts_with("Request Error")));
+        // both tries time out after a second each
+        assert!(started.elapsed() >= Duration::from_secs(2));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_powerflow() -> anyhow::Result<()> {
+        // shaped like the `/status/powerflow` response of a GEN24 with battery and a second pv inverter
+        let json: SolarJson = serde_json::from_str(r#"{
+            "common": {"datestamp": "16.10.2026", "timestamp": "12:00:00"},
+            "inverters": [{"BatMode": 1.0, "CID": 0, "DT": 1, "ID": 1, "P": 3100.4, "SOC": 55.5}],
+            "secondaryMeters": {"1": {"Category": "METER_CAT_PV", "Label": "Old inverter", "MLoc": 3, "P": 850.0}},
+            "site": {
+                "BackupMode": false, "BatteryStandby": false, "E_Day": null, "E_Total": 1234567.0, "E_Year": null,
+                "MLoc": 0, "Mode": "bidirectional", "P_Akku": -500.2, "P_Grid": -1200.5, "P_Load": -1400.3,
+                "P_PV": 3100.4, "rel_Autonomy": 100.0, "rel_SelfConsumption": 61.3
+            },
+            "version": "13"
+        }"#)?;
+        assert!((json.site.power_battery + 500.2).abs() < f64::EPSILON);
+        assert!((json.site.power_grid + 1200.5).abs() < f64::EPSILON);
+        assert!((json.site.house_consumption + 1400.3).abs() < f64::EPSILON);
+        assert!((json.site.power_pv - 3100.4).abs() < f64::EPSILON);
+        assert!((json.site.autonomy - 100.0).abs() < f64::EPSILON);
+        assert!((json.site.self_consumption - 61.3).abs() < f64::EPSILON);
+        assert_eq!((json.site.energy_day, json.site.energy_total), (None, Some(1_234_567.0)));
+        assert!((json.inverters[0].battery_percent - 55.5).abs() < f64::EPSILON);
+        assert!(json.secondary_meters.get("1").is_some_and(|meter| (meter.power - 850.0).abs() < f64::EPSILON));
+        assert_eq!(json.head.status.code, 0);
+        let config = Config { timezone: chrono_tz::Tz::Europe__Berlin, ..Config::default() };
+        let time = inverter_time(&config, &json.head, &json.common);
+        assert_eq!(time, Utc.with_ymd_and_hms(2026, 10, 16, 10, 0, 0).single());
+        Ok(())
+    }
+
+    #[test]
+    fn inverter_time_from_the_head() -> anyhow::Result<()> {
+        // shaped like the solar api of older firmwares
+        let json: HeadJson = serde_json::from_str(r#"{
+            "Head": {"Status": {"Code": 0}, "Timestamp": "2026-10-16T12:00:00+02:00"}
+        }"#)?;
+        // the head takes precedence over `common`
+        let common = Common { datestamp: Some("01.01.2000".to_owned()), timestamp: Some("00:00:00".to_owned()) };
+        let time = inverter_time(&Config::default(), &json.head, &common);
+        assert_eq!(time, Utc.with_ymd_and_hms(2026, 10, 16, 10, 0, 0).single());
+        assert_eq!(inverter_time(&Config::default(), &Head::default(), &Common::default()), None);
+        Ok(())
+    }
+
+    #[test]
+    fn clock_skew_above_the_warning() {
+        let config = Config { inverter_clock_skew_warn_secs: 60, ..Config::default() };
+        // an inverter running two minutes late, in both formats
+        let late = Utc::now() - chrono::Duration::seconds(120);
+        let head = Head { timestamp: Some(late.to_rfc3339()), ..Head::default() };
+        let common = Common {
+            datestamp: Some(late.format("%d.%m.%Y").to_string()),
+            timestamp: Some(late.format("%H:%M:%S").to_string()),
+        };
+        for skew in [clock_skew(&config, &head, &Common::default()), clock_skew(&config, &Head::default(), &common)] {
+            assert!(skew.is_some_and(|skew| (120..125).contains(&skew)
+                && skew.unsigned_abs() > config.inverter_clock_skew_warn_secs));
+        }
+    }
+
+    #[tokio::test]
+    async fn meter_request_answers_the_digest_challenge() -> anyhow::Result<()> {
+        let meter = r#"{"Body": {"Data": {"0": {
+            "Meter_Location_Current": 0, "Voltage_AC_Phase_1": 231.5, "Voltage_AC_Phase_2": 230.1,
+            "Voltage_AC_Phase_3": 229.8, "Current_AC_Phase_1": 1.5, "Current_AC_Phase_2": 2.5, "Current_AC_Phase_3": 3.5
+        }}}}"#;
+        let url = mock_http(Duration::ZERO, move |number, request| {
+            if number == 0 {
+                return MockResponse::new(401, "")
+                    .header("WWW-Authenticate", r#"Digest realm="Webinterface area", nonce="abc", qop="auth""#);
+            }
+            let authorized = request.to_lowercase().contains(r#"authorization: digest username="admin""#);
+            if request.starts_with("POST /solar_api/v1/GetMeterRealtimeData.cgi?Scope=System ")
+                && authorized && request.ends_with(r#"{"meter":true}"#) {
+                MockResponse::new(200, meter)
+            } else {
+                MockResponse::new(400, request)
+            }
+        }).await?;
+        let config = Config {
+            inverter_auth: InverterAuth::Digest,
+            inverter_username: Some("admin".to_owned()),
+            inverter_password: Some("secret".to_owned()),
+            inverter_method: InverterMethod::Post,
+            inverter_request_body: Some(r#"{"meter":true}"#.to_owned()),
+            ..Config::default()
+        };
+        let phases = fetch_grid_phases(&config, &reqwest::Client::new(), &url).await?;
+        assert!((phases.u1 - 231.5).abs() < f64::EPSILON);
+        assert!((phases.i3 - 3.5).abs() < f64::EPSILON);
+        Ok(())
+    }
+
+    #[test]
+    fn device_id_per_url() -> anyhow::Result<()> {
+        let first = Url::parse("http://192.168.1.10")?;
+        let second = Url::parse("http://192.168.1.11")?;
+        let config = Config { inverter_url: format!("{first}, {second}"), ..Config::default() };
+        assert_eq!((device_id(&config, &first)?, device_id(&config, &second)?), (1, 1));
+
+        let separate = Config { inverter_device_ids: "1, 3".to_owned(), ..config.clone() };
+        assert_eq!((device_id(&separate, &first)?, device_id(&separate, &second)?), (1, 3));
+
+        for invalid in ["1, 2, 3", "a", ""] {
+            let config = Config { inverter_device_ids: invalid.to_owned(), ..config.clone() };
+            assert!(inverter_device_ids(&config).is_err());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn error_status_in_ok_response_is_retried() -> anyhow::Result<()> {
+        let failed = r#"{"Head": {"Status": {"Code": 8, "Reason": "Transfer timeout", "UserMessage": ""}}, "Body": {}}"#;
+        let powerflow = r#"{"inverters": [{"SOC": 40}], "site": {"P_Akku": 0, "P_Grid": 200, "P_Load": -700, "P_PV": 500,
+            "rel_Autonomy": 71, "rel_SelfConsumption": 100}}"#;
+        let config = Config { inverter_retries: 2, inverter_retry_delay_ms: 10, ..Config::default() };
+        let client = reqwest::Client::new();
+
+        let recovering = mock_http(Duration::ZERO, move |number, _| {
+            MockResponse::new(200, if number == 0 { failed } else { powerflow })
+        }).await?;
+        let (json, raw) = fetch_powerflow(&config, &client, &recovering).await?;
+        assert!((json.site.power_grid - 200.0).abs() < f64::EPSILON);
+        assert_eq!(raw, powerflow);
+
+        let failing = mock_http(Duration::ZERO, move |_, _| MockResponse::new(200, failed)).await?;
+        let result = fetch_powerflow(&config, &client, &failing).await;
+        assert!(result.is_err_and(|err| err.to_string() == "Inverter Error: 8, Transfer timeout"));
+        Ok(())
+    }
+
+    #[test]
+    fn self_sufficiency_without_load() {
+        assert_eq!(self_sufficiency(0.0, 0.0), 100);
+        assert_eq!(self_sufficiency(0.0, 500.0), 100);
+        assert_eq!(self_sufficiency(-10.0, -3000.0), 100);
+    }
+
+    #[test]
+    fn self_sufficiency_while_exporting() {
+        // export is negative grid power and does not lower the share
+        assert_eq!(self_sufficiency(800.0, -4000.0), 100);
+        assert_eq!(self_sufficiency(800.0, 0.0), 100);
+    }
+
+    #[test]
+    fn self_sufficiency_while_importing() {
+        assert_eq!(self_sufficiency(1000.0, 250.0), 75);
+        assert_eq!(self_sufficiency(1000.0, 1000.0), 0);
+        // more import than consumption, e.g. while the battery charges from the grid
+        assert_eq!(self_sufficiency(1000.0, 3000.0), 0);
+    }
+
+    /// site values as reported by the powerflow api, without consumption and percentages
+    fn site(pv: f64, grid: f64, battery: f64) -> Site {
+        Site {
+            power_battery: battery,
+            power_grid: grid,
+            house_consumption: 0.0,
+            power_pv: pv,
+            autonomy: 0.0,
+            self_consumption: 0.0,
+            energy_day: None,
+            energy_year: None,
+            energy_total: None,
+        }
+    }
+
+    #[test]
+    fn reconstruct_consumption_sums_the_sources() {
+        // pv plus grid import plus battery discharge
+        assert!((reconstruct_consumption(&site(2000.0, 300.0, 500.0)) - 2800.0).abs() < f64::EPSILON);
+        // export and charging reduce what is left for the house
+        assert!((reconstruct_consumption(&site(5000.0, -2500.0, -1500.0)) - 1000.0).abs() < f64::EPSILON);
+        assert!((reconstruct_consumption(&site(0.0, 400.0, 0.0)) - 400.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn reconstruct_consumption_is_never_negative() {
+        // measurement errors between the meters can add up to a negative sum
+        assert!(reconstruct_consumption(&site(1000.0, -1100.0, 0.0)).abs() < f64::EPSILON);
+        assert!(reconstruct_consumption(&site(0.0, -50.0, -20.0)).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn freshness_follows_the_poll_interval() {
+        let solar = SolarData { last_time: OffsetDateTime::now_utc() - time::Duration::seconds(45), ..SolarData::default() };
+        let slow = Config { poll_interval_secs: 30, inverter_timeout_secs: 5, ..Config::default() };
+        let fast = Config { poll_interval_secs: 10, inverter_timeout_secs: 5, ..Config::default() };
+        assert!(solar.is_fresh(&slow));
+        assert!(!solar.is_fresh(&fast));
+    }
+}
@@ -17,27 +17,45 @@ clippy::wildcard_enum_match_arm
 
 extern crate core;
 
-use std::{env, io};
-use std::env::consts::ARCH;
-use std::io::BufRead;
+use std::{env, fs, io};
+use std::io::{BufRead, IsTerminal};
 use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
 use std::time::Duration;
 
-use anyhow::{ensure, Result};
-use poem::{EndpointExt, Route, Server};
+use anyhow::{anyhow, bail, Result};
+use poem::{Endpoint, EndpointExt, get, Route, Server};
 use poem::listener::TcpListener;
 use poem::middleware::Cors;
 use poem_openapi::OpenApiService;
 use time::OffsetDateTime;
-use tokio::spawn;
-use tokio::sync::RwLock;
-use tokio::time::sleep;
+use tokio::{select, spawn};
+use tokio::signal::ctrl_c;
+use tokio::task::JoinSet;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio::time::{sleep, timeout};
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
+use url::Url;
 
-use crate::api::SolarApi;
-use crate::config::{Config, load};
-use crate::inverter::SolarData;
-use crate::utils::add_point;
+use crate::aggregate::parse_aggregations;
+use crate::auth::check_api_key;
+use crate::api::{HealthApi, InfluxApi, InverterApi, RefreshApi, SolarApi, StreamApi, VersionApi, WattpilotApi};
+use crate::config::{Config, load, log_sources, LogFormat};
+use crate::digest::InverterAuth;
+use crate::inverter::{fetch_solar_values, inverter_device_ids, inverter_urls, InverterKind, SolarData};
+use crate::influx::{InfluxVersion, LABEL_TAG_FIELDS, parse_tags};
+use crate::export::get_solar_csv;
+use crate::metrics::get_metrics;
+use crate::mock::MockInverter;
+use crate::plausibility::{Bound, parse_bounds};
+use crate::ratelimit::{limit_rate, RateLimiter};
+use crate::sink::{Sink, sinks};
+use crate::stats::{DailyStats, RollingSamples};
+use crate::utils::{add_point, MonitoringBreaker, poll_wait, write_points};
 use crate::wattpilot::{Wattpilot, WattpilotData};
 
 mod config;
@@ -45,119 +63,358 @@ mod utils;
 mod api;
 mod wattpilot;
 mod inverter;
+mod solaredge;
+mod mock;
+mod digest;
+mod meter;
+mod plausibility;
+mod sink;
+mod influx;
+mod aggregate;
+mod postgres;
+mod metrics;
+mod auth;
+mod export;
+mod stats;
+mod history;
+mod ratelimit;
 
 #[derive(Clone)]
 struct AppState {
     config: Arc<Config>,
     solar_data: Arc<RwLock<SolarData>>,
-    wattpilot_data: Arc<RwLock<WattpilotData>>
+    inverter_raw: Arc<RwLock<Vec<String>>>,
+    wattpilots: Arc<Vec<Arc<RwLock<Wattpilot>>>>,
+    wattpilot_data: Arc<RwLock<WattpilotData>>,
+    monitoring_breaker: Arc<RwLock<MonitoringBreaker>>,
+    sinks: Arc<Vec<Arc<dyn Sink>>>,
+    inverter_urls: Arc<Vec<Url>>,
+    /// simulated inverter, only used with `mock`
+    mock_inverter: Arc<MockInverter>,
+    /// parsed `plausibility_bounds`
+    plausibility_bounds: Arc<Vec<Bound>>,
+    /// how often the scheduled inverter query failed
+    inverter_failures: Arc<AtomicU64>,
+    /// extrema of the solar values of the current day
+    daily_stats: Arc<RwLock<DailyStats>>,
+    /// polls of the last 15 minutes, for the rolling averages
+    rolling_samples: Arc<RwLock<RollingSamples>>,
+    /// notified after every successful scheduled fetch
+    updates: broadcast::Sender<()>,
+    client: reqwest::Client,
+    /// client for the inverter, may accept invalid certificates
+    inverter_client: reqwest::Client,
+    started: OffsetDateTime
 }
 
 #[tokio::main]
+#[allow(clippy::too_many_lines)]
 async fn start() -> Result<()> {
-    if env::var("RUST_LOG").is_err() {
-        env::set_var("RUST_LOG", "info");
-    }
-    tracing_subscriber::fmt::init();
+    // the log settings are part of the config, errors while loading it are logged with the defaults
+    let loaded = load();
+    let defaults = Config::default();
+    let log_config = loaded.as_ref().map_or(&defaults, |(config, _)| config);
+    init_logging(&log_config.log_level, log_config.log_format);
 
     // check config values
-    let mut config = load()?;
-    ensure!(
-        config.influx_url.is_some(),
-        "Influx url should be set!"
-    );
-    ensure!(
-        config.influx_measurement.is_some(),
-        "Influx measurement should be set!"
-    );
-    ensure!(
-         config.influx_token.is_some(),
-        "Influx token should be set!"
-    );
-    ensure!(
-        config.inverter_url.is_some(),
-        "Inverter url should be set!"
-    );
-    ensure!(
-        config.healthcheck_url.is_some(),
-        "Healthchecks url should be set!"
-    );
-    if config.wattpilot_password.is_none() {
-        println!("Wattpilot Passwort? ");
-        let stdin = io::stdin();
-        if let Some(Ok(line)) = stdin.lock().lines().next() {
-            if line.is_empty() {
-                warn!("No Wattpilot password given, feature deactivated!");
+    let (mut config, file_keys) = loaded?;
+    log_sources(&config, &file_keys)?;
+    validate(&config)?;
+    let mut urls = inverter_urls(&config)?;
+    let bounds = parse_bounds(&config.plausibility_bounds)?;
+    if config.inverter_kind == InverterKind::Mock && urls.is_empty() {
+        // the simulated inverter needs no url
+        urls.push(Url::parse("mock://inverter")?);
+    }
+    if config.api_key.is_none() {
+        warn!("No api key set, mutating endpoints are not protected!");
+    }
+    if let Some(path) = &config.wattpilot_password_file {
+        config.wattpilot_password = Some(read_password_file(path)?);
+        config.wattpilot_passwords.clear();
+    } else if config.wattpilot_password.is_none() && config.wattpilot_passwords.is_empty() {
+        if io::stdin().is_terminal() {
+            println!("Wattpilot Passwort? ");
+            let stdin = io::stdin();
+            if let Some(Ok(line)) = stdin.lock().lines().next() {
+                if line.is_empty() {
+                    warn!("No Wattpilot password given, feature deactivated!");
+                } else {
+                    config.wattpilot_password = Some(line);
+                }
             } else {
-                config.wattpilot_password = Some(line);
+                warn!("No Wattpilot password given, feature deactivated!");
             }
         } else {
             warn!("No Wattpilot password given, feature deactivated!");
         }
     }
+    Wattpilot::devices(&config)?;
 
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.http_timeout_secs))
+        .build()?;
+    let inverter_client = if config.inverter_accept_invalid_certs {
+        warn!("Accepting invalid certificates of the inverter");
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.http_timeout_secs))
+            .danger_accept_invalid_certs(true)
+            .build()?
+    } else {
+        client.clone()
+    };
     let solar_data = Arc::new(RwLock::new(SolarData::default()));
-    let wattpilot = Wattpilot::new(&config);
-    let wp_clone;
-    let wp_data_clone = match wattpilot {
-        None => {
-            wp_clone = None;
-            Arc::default()
-        }
-        Some(wp) => {
-            wp_clone = Some(Arc::clone(&wp));
-            Arc::clone(&wp.read().await.data)
-        }
+    let mut wattpilot_handlers = JoinSet::new();
+    let shutdown = CancellationToken::new();
+    let wattpilots = Wattpilot::new(&config, &mut wattpilot_handlers, &shutdown);
+    let wp_data_clone = match wattpilots.first() {
+        None => Arc::default(),
+        Some(wp) => Arc::clone(&wp.read().await.data),
+    };
+
+    let server_url = format!("{}:{}", config.app_host.clone(), config.app_port.clone());
+    let base_path = config.base_path.trim_end_matches('/').to_owned();
+
+    // create var to carry db connection
+    let sinks = Arc::new(sinks(&config, &client));
+    let state = AppState {
+        solar_data,
+        inverter_raw: Arc::default(),
+        wattpilots: Arc::new(wattpilots),
+        wattpilot_data: wp_data_clone,
+        monitoring_breaker: Arc::default(),
+        sinks: Arc::clone(&sinks),
+        inverter_urls: Arc::new(urls),
+        mock_inverter: Arc::new(MockInverter::new(&config)),
+        plausibility_bounds: Arc::new(bounds),
+        inverter_failures: Arc::new(AtomicU64::new(0)),
+        daily_stats: Arc::new(RwLock::new(DailyStats::default())),
+        rolling_samples: Arc::new(RwLock::new(RollingSamples::new(&config))),
+        updates: broadcast::channel(1).0,
+        client,
+        inverter_client,
+        started: OffsetDateTime::now_utc(),
+        config: Arc::new(config)
     };
 
-    // setup querying of Fronius and adding of data to db
-    let config_clone = config.clone();
-    let solar_data_clone = solar_data.clone();
-    spawn(async move {
+    // setup querying of the inverter and adding of data to db
+    let (writer, receiver) = mpsc::channel(state.config.write_queue_size);
+    let writer_handle = spawn(write_points(state.clone(), receiver));
+    let state_clone = state.clone();
+    let poll_handle = spawn(async move {
+        if state_clone.config.fetch_on_start {
+            // only fill the api, points are written at the aligned ticks
+            if let Err(err) = fetch_solar_values(&state_clone).await {
+                warn!("Could not fetch solar values on start: {err}");
+            }
+        }
         loop {
-            let now = OffsetDateTime::now_utc();
-            let wait = u16::from(9 - now.second() % 10) * 1000 + 1000 - now.millisecond() % 1000;
-            sleep(Duration::from_millis(u64::from(wait))).await;
-            add_point(&config_clone, &solar_data_clone, &wp_clone).await;
+            sleep(poll_wait(&state_clone.config)).await;
+            add_point(&state_clone, &writer).await;
         }
     });
 
-    let server_url = format!("{}:{}", config.app_host.clone(), config.app_port.clone());
-    let origins = config.allowed_origins.clone();
+    let route = app(state);
+
+    // run server
+    info!("Starting server at http://{}{}", server_url, base_path);
+    Server::new(TcpListener::bind(server_url))
+        .run_with_graceful_shutdown(route, shutdown_signal(), Some(Duration::from_secs(5)))
+        .await?;
+
+    // stop polling and write the queued points; the writer stops once the poll loop dropped its sender
+    info!("Server stopped, writing queued points");
+    poll_handle.abort();
+    let _ = poll_handle.await;
+    if timeout(Duration::from_secs(10), writer_handle).await.is_err() {
+        warn!("Queued points were not written in time");
+    }
+
+    // write held back points
+    info!("Flushing sinks");
+    for sink in sinks.iter() {
+        if let Err(err) = sink.flush().await {
+            error!("Could not flush {}: {err}", sink.name());
+        }
+    }
+
+    // close the wattpilot connections
+    info!("Closing wattpilot connections");
+    shutdown.cancel();
+    let handlers_done = async {
+        while wattpilot_handlers.join_next().await.is_some() {}
+    };
+    if timeout(Duration::from_secs(5), handlers_done).await.is_err() {
+        warn!("Wattpilot connections did not close in time");
+    }
+    Ok(())
+}
+
+/// build the routes of the server with all middlewares, mounted at `base_path`
+fn app(state: AppState) -> impl Endpoint {
+    let config = Arc::clone(&state.config);
+    let swagger_ui_path = config.swagger_ui_enabled.then(|| config.swagger_ui_path.clone());
+    let base_path = config.base_path.trim_end_matches('/').to_owned();
 
     // create api service and needed routes
     let mut api_service = OpenApiService::new(
-        SolarApi,
+        (SolarApi, RefreshApi, StreamApi, InverterApi, InfluxApi, WattpilotApi, HealthApi, VersionApi),
         "HomeserverApi",
         env!("CARGO_PKG_VERSION"),
     );
-    for server in config.swagger_servers.split(',').map(str::trim).filter(|s| !s.is_empty()) {
-        api_service = api_service.server(server);
+    let servers: Vec<&str> = config.swagger_servers.split(',').map(str::trim).filter(|s| !s.is_empty()).collect();
+    for server in &servers {
+        api_service = api_service.server(format!("{}{base_path}", server.trim_end_matches('/')));
+    }
+    if servers.is_empty() && !base_path.is_empty() {
+        api_service = api_service.server(base_path.clone());
     }
-    // create var to carry db connection
-    let state = AppState { config: Arc::new(config), solar_data, wattpilot_data: wp_data_clone};
     let ui = api_service.swagger_ui();
     let spec = api_service.spec();
+    let limiter = Arc::new(RateLimiter::new(config.rate_limit_per_minute, format!("{base_path}/api/health")));
     let api_route = Route::new()
         .nest_no_strip("/api", api_service)
-        .data(state);
-    let ui_route = Route::new().at("/", ui);
+        .data(state.clone());
 
     // create routes for all things
-    let route = Route::new()
+    let mut route = Route::new()
         .nest_no_strip("/api", api_route)
-        .at("/spec", poem::endpoint::make_sync(move |_| spec.clone()))
-        .at("/", ui_route)
-        .with(Cors::new().allow_origins(origins.split(',').map(str::trim).filter(|s| !s.is_empty())));
+        .at("/api/solar.csv", get(get_solar_csv).data(state.clone()))
+        .at("/metrics", get(get_metrics).data(state));
+    if config.spec_enabled {
+        route = route.at("/spec", poem::endpoint::make_sync(move |_| spec.clone()));
+    }
+    if let Some(path) = swagger_ui_path {
+        route = route.nest(path, ui);
+    }
+    if !base_path.is_empty() {
+        route = Route::new().nest(base_path.as_str(), route);
+    }
+    let origins: Vec<String> = config.allowed_origins.split(',').map(str::trim).filter(|s| !s.is_empty())
+        .map(str::to_owned).collect();
+    route
+        .around(move |next, req| check_api_key(next, req, config.clone()))
+        .around(move |next, req| limit_rate(next, req, limiter.clone()))
+        .with(Cors::new().allow_origins(origins))
+}
 
-    // run server
-    info!("Starting server at http://{}", server_url);
-    Server::new(TcpListener::bind(server_url))
-        .run(route)
-        .await?;
+/// read the wattpilot password from a file, e.g. a mounted secret; trailing line breaks are not part of it
+fn read_password_file(path: &str) -> Result<String> {
+    let password = fs::read_to_string(path)
+        .map_err(|err| anyhow!("Could not read wattpilot password file {path}: {err}"))?;
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        bail!("Wattpilot password file {path} is empty");
+    }
+    Ok(password.to_owned())
+}
+
+/// set up the log output; `RUST_LOG` overrides the configured level
+fn init_logging(level: &str, format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Full => builder.init(),
+        LogFormat::Pretty => builder.pretty().init(),
+        LogFormat::Compact => builder.compact().init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+/// check the config values, reporting all problems at once
+fn validate(config: &Config) -> Result<()> {
+    let checks = [
+        (config.influx_url.is_some(), "Influx url should be set!"),
+        (
+            config.influx_url.as_ref().is_none_or(|url| url.query_pairs()
+                .all(|(key, value)| key != "precision" || value == config.influx_precision.name())),
+            "Influx precision should match the precision in the influx url!",
+        ),
+        (config.influx_org.is_some() == config.influx_bucket.is_some(), "Influx org and bucket should be set together!"),
+        (config.influx_measurement.is_some(), "Influx measurement should be set!"),
+        (config.influx_token.is_some() || config.influx_version == InfluxVersion::V1, "Influx token should be set!"),
+        (config.influx_retries > 0, "Influx retries should be at least 1!"),
+        (config.poll_interval_secs > 0, "Poll interval should be at least 1 second!"),
+        (config.write_queue_size > 0, "Write queue size should be at least 1!"),
+        (
+            config.influx_batch_size > 0 && config.influx_buffer_max >= config.influx_batch_size,
+            "Influx batch size should be at least 1 and at most the buffer max!",
+        ),
+        (
+            config.inverter_kind != InverterKind::SolarEdge
+                || (config.solaredge_site_id.is_some() && config.solaredge_api_key.is_some()),
+            "SolarEdge site id and api key should be set for solaredge inverters!",
+        ),
+        (config.inverter_retries > 0, "Inverter retries should be at least 1!"),
+        (
+            config.inverter_auth == InverterAuth::None
+                || (config.inverter_username.is_some() && config.inverter_password.is_some()),
+            "Inverter username and password should be set for inverter auth!",
+        ),
+        (config.base_path.is_empty() || config.base_path.starts_with('/'), "Base path should start with a slash!"),
+        (config.swagger_ui_path.starts_with('/'), "Swagger ui path should start with a slash!"),
+    ];
+    let mut errors: Vec<String> = checks.into_iter()
+        .filter(|(valid, _)| !valid)
+        .map(|(_, message)| message.to_owned())
+        .collect();
+    if !config.influx_label_tags.split(',').map(str::trim).filter(|s| !s.is_empty())
+        .all(|field| LABEL_TAG_FIELDS.contains(&field)) {
+        errors.push(format!("Influx label tags may only contain: {}", LABEL_TAG_FIELDS.join(", ")));
+    }
+    match parse_aggregations(&config.influx_aggregations) {
+        Err(err) => errors.push(err.to_string()),
+        Ok(aggregations) if config.influx_aggregate_window_secs > 0 && aggregations.is_empty() => {
+            errors.push("Influx aggregations should be set when aggregating!".to_owned());
+        }
+        Ok(_) => {}
+    }
+    match inverter_urls(config) {
+        Err(err) => errors.push(err.to_string()),
+        Ok(urls) if urls.is_empty() && config.inverter_kind != InverterKind::Mock => {
+            errors.push("Inverter url should be set!".to_owned());
+        }
+        Ok(_) => {}
+    }
+    for result in [
+        parse_tags(&config.influx_tags).map(drop),
+        parse_bounds(&config.plausibility_bounds).map(drop),
+        inverter_device_ids(config).map(drop),
+    ] {
+        if let Err(err) = result {
+            errors.push(err.to_string());
+        }
+    }
+    if !errors.is_empty() {
+        bail!("Invalid config:\n{}", errors.join("\n"));
+    }
     Ok(())
 }
 
+/// resolves when the application should shut down (SIGTERM or Ctrl+C)
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                select! {
+                    _ = ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(err) => {
+                error!("Could not listen for SIGTERM: {err}");
+                let _ = ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = ctrl_c().await;
+    }
+    info!("Shutting down");
+}
+
 pub fn main() {
     let result = start();
 
@@ -166,3 +423,164 @@ pub fn main() {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use poem::http::StatusCode;
+    use poem::Request;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    /// app state without inverters, wattpilots and sinks
+    pub(crate) fn state(config: Config) -> AppState {
+        AppState {
+            solar_data: Arc::default(),
+            inverter_raw: Arc::default(),
+            wattpilots: Arc::default(),
+            wattpilot_data: Arc::default(),
+            monitoring_breaker: Arc::default(),
+            sinks: Arc::default(),
+            inverter_urls: Arc::default(),
+            mock_inverter: Arc::new(MockInverter::new(&config)),
+            plausibility_bounds: Arc::default(),
+            inverter_failures: Arc::default(),
+            daily_stats: Arc::default(),
+            rolling_samples: Arc::new(RwLock::new(RollingSamples::new(&config))),
+            updates: broadcast::channel(1).0,
+            client: reqwest::Client::new(),
+            inverter_client: reqwest::Client::new(),
+            started: OffsetDateTime::now_utc(),
+            config: Arc::new(config),
+        }
+    }
+
+    /// response of `mock_http`
+    pub(crate) struct MockResponse {
+        status: u16,
+        headers: Vec<(&'static str, String)>,
+        body: String,
+    }
+
+    impl MockResponse {
+        pub(crate) fn new(status: u16, body: &str) -> Self {
+            MockResponse { status, headers: Vec::new(), body: body.to_owned() }
+        }
+
+        pub(crate) fn header(mut self, name: &'static str, value: &str) -> Self {
+            self.headers.push((name, value.to_owned()));
+            self
+        }
+    }
+
+    /// http server answering every request with the response returned by `respond`, after `delay`;\
+    /// `respond` gets the number of the request and the request, with head and body; returns the url of the server
+    pub(crate) async fn mock_http<F>(delay: Duration, respond: F) -> Result<Url>
+    where
+        F: Fn(usize, &str) -> MockResponse + Send + Sync + 'static,
+    {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = Url::parse(&format!("http://{}/", listener.local_addr()?))?;
+        let respond = Arc::new(respond);
+        spawn(async move {
+            let mut count = 0;
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let respond = Arc::clone(&respond);
+                let number = count;
+                count += 1;
+                spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buffer = [0u8; 4096];
+                    loop {
+                        let Ok(read) = stream.read(&mut buffer).await else {
+                            return;
+                        };
+                        if read == 0 {
+                            return;
+                        }
+                        request.extend_from_slice(&buffer[..read]);
+                        let text = String::from_utf8_lossy(&request);
+                        let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                            continue;
+                        };
+                        let length = head.lines()
+                            .filter_map(|line| line.split_once(':'))
+                            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                            .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+                            .unwrap_or_default();
+                        if body.len() >= length {
+                            break;
+                        }
+                    }
+                    sleep(delay).await;
+                    let response = respond(number, &String::from_utf8_lossy(&request));
+                    let headers = response.headers.iter()
+                        .map(|(name, value)| [*name, ": ", value, "\r\n"].concat())
+                        .collect::<String>();
+                    let response = format!(
+                        "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n{headers}\r\n{}",
+                        response.status,
+                        response.body.len(),
+                        response.body,
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        Ok(url)
+    }
+
+    /// status of a GET request against the app
+    async fn status(app: &impl Endpoint, path: &str) -> StatusCode {
+        app.get_response(Request::builder().uri_str(path).finish()).await.status()
+    }
+
+    #[tokio::test]
+    async fn routes_with_base_path() {
+        let app = app(state(Config {
+            base_path: "/solar/".to_owned(),
+            swagger_ui_path: "/docs".to_owned(),
+            ..Config::default()
+        }));
+        assert_eq!(status(&app, "/solar/api/version").await, StatusCode::OK);
+        assert_eq!(status(&app, "/solar/api/solar.csv").await, StatusCode::OK);
+        assert_eq!(status(&app, "/solar/metrics").await, StatusCode::OK);
+        assert_eq!(status(&app, "/solar/spec").await, StatusCode::OK);
+        assert_eq!(status(&app, "/solar/docs").await, StatusCode::OK);
+        assert_eq!(status(&app, "/api/version").await, StatusCode::NOT_FOUND);
+        assert_eq!(status(&app, "/metrics").await, StatusCode::NOT_FOUND);
+        assert_eq!(status(&app, "/spec").await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn routes_without_base_path() {
+        let app = app(state(Config::default()));
+        assert_eq!(status(&app, "/api/version").await, StatusCode::OK);
+        assert_eq!(status(&app, "/metrics").await, StatusCode::OK);
+        assert_eq!(status(&app, "/spec").await, StatusCode::OK);
+        assert_eq!(status(&app, "/solar/api/version").await, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn password_file_ignores_trailing_line_breaks() -> Result<()> {
+        let dir = env::temp_dir().join(format!("wattpilot-password-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("password");
+        let path_str = path.to_string_lossy();
+        for content in ["pw", "pw\n", "pw\r\n", "pw\n\n"] {
+            fs::write(&path, content)?;
+            assert_eq!(read_password_file(&path_str)?, "pw");
+        }
+        // the password is never split into lines
+        fs::write(&path, "first\nsecond\n")?;
+        assert_eq!(read_password_file(&path_str)?, "first\nsecond");
+
+        for content in ["", "\n", "\r\n\n"] {
+            fs::write(&path, content)?;
+            assert!(read_password_file(&path_str).is_err());
+        }
+        assert!(read_password_file(&dir.join("missing").to_string_lossy()).is_err());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
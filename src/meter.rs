@@ -0,0 +1,50 @@
+//! Optional separate meters for the consumption of the whole house
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use url::Url;
+
+use crate::config::Config;
+use crate::utils::deserialize_null_default;
+
+/// a meter which measures the consumption of the whole house
+pub(crate) trait ConsumptionMeter {
+    /// current consumption of the whole house; data in watts
+    async fn consumption(&self, client: &reqwest::Client) -> Result<f64>;
+}
+
+#[derive(Deserialize, Debug)]
+struct ShellyEmeter {
+    /// current power of this channel; data in watts
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    power: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct ShellyStatus {
+    emeters: Vec<ShellyEmeter>,
+}
+
+/// Shelly EM / 3EM (gen 1), all channels are summed up
+pub(crate) struct ShellyEm {
+    url: Url,
+}
+
+impl ConsumptionMeter for ShellyEm {
+    async fn consumption(&self, client: &reqwest::Client) -> Result<f64> {
+        let resp = client
+            .get(self.url.join("/status")?)
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            return Err(anyhow!("Response Error: {}, {}", resp.status(), resp.text().await?));
+        }
+        let status: ShellyStatus = serde_json::from_str(resp.text().await?.as_str())?;
+        Ok(status.emeters.iter().map(|emeter| emeter.power).sum())
+    }
+}
+
+/// get the configured consumption meter, if any
+pub(crate) fn consumption_meter(config: &Config) -> Option<ShellyEm> {
+    config.consumption_meter_url.clone().map(|url| ShellyEm { url })
+}
@@ -0,0 +1,85 @@
+//! prometheus exposition of the current values
+
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+
+use poem::handler;
+use poem::web::Data;
+
+use crate::AppState;
+
+/// append one metric with its help and type lines; every sample is a label set and a value
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, f64)]) {
+    if samples.is_empty() {
+        return;
+    }
+    // writing to a string can not fail
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{name}{labels} {value}");
+    }
+}
+
+/// a single sample without labels
+fn single(value: f64) -> Vec<(String, f64)> {
+    vec![(String::new(), value)]
+}
+
+/// escape a label value of the exposition format
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// get the current values in the prometheus text format
+#[handler]
+#[allow(clippy::cast_precision_loss)]
+pub(crate) async fn get_metrics(state: Data<&AppState>) -> String {
+    let mut out = String::new();
+    let solar = state.solar_data.read().await.clone();
+    let gauges = [
+        ("solar_new_inverter_power_watts", "power produced by the new pv system", Some(f64::from(solar.new_inverter_power))),
+        ("solar_old_inverter_power_watts", "power produced by the old pv system", solar.old_inverter_power.map(f64::from)),
+        ("solar_battery_load_percent", "charge of the battery", Some(f64::from(solar.battery_load_percentage))),
+        ("solar_house_consumption_watts", "power consumed by the house", Some(solar.house_consumption as f64)),
+        ("solar_drain_from_battery_watts", "power drained from the battery, negative when charging", Some(solar.drain_from_battery as f64)),
+        ("solar_drain_from_grid_watts", "power drawn from the grid, negative when feeding in", Some(solar.drain_from_grid as f64)),
+        ("solar_grid_import_watts", "power drawn from the grid", Some(solar.grid_import_watts as f64)),
+        ("solar_grid_export_watts", "power fed into the grid", Some(solar.grid_export_watts as f64)),
+        ("solar_self_consumption_percent", "share of the produced power used in the house", Some(f64::from(solar.self_consumption_percent))),
+        ("solar_self_sufficiency_percent", "share of the consumption not drawn from the grid", Some(f64::from(solar.self_sufficiency_percent))),
+        ("solar_coverage_percent", "share of the consumption covered by the pv systems", Some(f64::from(solar.solar_coverage_percent))),
+        ("solar_last_update_timestamp_seconds", "time of the last successful inverter query", Some(solar.last_time.unix_timestamp() as f64)),
+    ];
+    for (name, help, value) in gauges {
+        if let Some(value) = value {
+            metric(&mut out, name, "gauge", help, &single(value));
+        }
+    }
+    metric(
+        &mut out,
+        "solar_inverter_fetch_failures_total",
+        "counter",
+        "how often the inverter could not be queried",
+        &single(state.inverter_failures.load(Ordering::Relaxed) as f64),
+    );
+
+    let mut power = Vec::new();
+    let mut energy = Vec::new();
+    let mut authenticated = Vec::new();
+    let mut reconnects = Vec::new();
+    for wp in state.wattpilots.iter() {
+        let read = wp.read().await;
+        let data = read.data.read().await;
+        let labels = format!("{{id=\"{}\"}}", escape_label(&read.id));
+        power.push((labels.clone(), f64::from(data.charging_values.pt)));
+        energy.push((labels.clone(), data.total_energy_wh as f64));
+        authenticated.push((labels.clone(), f64::from(u8::from(read.authenticated))));
+        reconnects.push((labels, read.reconnect_count as f64));
+    }
+    metric(&mut out, "wattpilot_power_watts", "gauge", "charging power of the wattpilot", &power);
+    metric(&mut out, "wattpilot_total_energy_wh", "gauge", "energy charged over the lifetime of the wattpilot", &energy);
+    metric(&mut out, "wattpilot_authenticated", "gauge", "whether the wattpilot is authenticated", &authenticated);
+    metric(&mut out, "wattpilot_reconnects_total", "counter", "how often the websocket was reconnected", &reconnects);
+    out
+}
@@ -0,0 +1,104 @@
+//! Simulated inverter for development without hardware
+
+use std::f64::consts::PI;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde_json::json;
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::config::Config;
+use crate::inverter::{Inverter, SolarData, self_sufficiency, solar_coverage};
+
+/// pv power at noon; data in watts
+const PEAK_POWER: f64 = 8000.0;
+/// maximum power the battery is charged or discharged with; data in watts
+const BATTERY_POWER: f64 = 3000.0;
+
+/// simulated inverter; pv power follows a sine curve from 6:00 to 18:00 (UTC), the house load is random
+pub(crate) struct MockInverter {
+    /// seeded once, so the same seed always gives the same sequence of values
+    rng: Mutex<StdRng>,
+}
+
+impl MockInverter {
+    pub(crate) fn new(config: &Config) -> Self {
+        let rng = match config.mock_seed {
+            None => StdRng::from_entropy(),
+            Some(seed) => StdRng::seed_from_u64(seed),
+        };
+        MockInverter { rng: Mutex::new(rng) }
+    }
+}
+
+impl Inverter for MockInverter {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    async fn fetch(&self, config: &Config, _client: &reqwest::Client, _url: &Url) -> Result<(SolarData, String)> {
+        let now = OffsetDateTime::now_utc();
+        let (variation, load) = {
+            let mut rng = self.rng.lock().map_err(|_| anyhow!("Mock inverter lock is poisoned"))?;
+            (rng.gen_range(0.9..1.0), rng.gen_range(300.0..2500.0))
+        };
+        let hour = f64::from(now.hour()) + f64::from(now.minute()) / 60.0;
+        let sine = (PI * (hour - 6.0) / 12.0).sin().max(0.0);
+        let pv = PEAK_POWER * sine * variation;
+        // grid: positive = import, battery: positive = discharge
+        let battery = (load - pv).clamp(-BATTERY_POWER, BATTERY_POWER);
+        let grid = load - pv - battery;
+        let self_consumption = if pv <= 0.0 { 0.0 } else { ((pv + grid.min(0.0)) / pv * 100.0).clamp(0.0, 100.0) };
+        let raw = json!({ "pv": pv, "load": load, "battery": battery, "grid": grid }).to_string();
+        Ok((SolarData {
+            last_time: now,
+            old_inverter_power: (!config.omit_missing_secondary_meter).then_some(0),
+            new_inverter_power: pv as u32,
+            both_inverter_power: (!config.omit_missing_secondary_meter).then_some(pv as u32),
+            battery_load_percentage: (50.0 + 40.0 * sine) as u8,
+            autonomy_percent: self_sufficiency(load, grid),
+            self_consumption_percent: self_consumption.round() as u8,
+            self_sufficiency_percent: self_sufficiency(load, grid),
+            drain_from_battery: battery as i64,
+            drain_from_grid: grid as i64,
+            house_consumption: load as u64,
+            grid_import_watts: grid.max(0.0) as u64,
+            grid_export_watts: (-grid).max(0.0) as u64,
+            solar_coverage_percent: solar_coverage(load, pv),
+            inverter_clock_skew: None,
+            ev_charging_power: None,
+            grid_phases: None,
+            energy_day: None,
+            energy_year: None,
+            energy_total: None,
+            battery_temperature: None,
+            inverter_status_code: None,
+            inverter_error_code: None,
+        }, raw))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// house consumption of the next `count` polls
+    async fn loads(inverter: &MockInverter, count: usize) -> Result<Vec<u64>> {
+        let (config, client, url) = (Config::default(), reqwest::Client::new(), Url::parse("mock://inverter")?);
+        let mut loads = Vec::new();
+        for _ in 0..count {
+            loads.push(inverter.fetch(&config, &client, &url).await?.0.house_consumption);
+        }
+        Ok(loads)
+    }
+
+    #[tokio::test]
+    async fn same_seed_gives_the_same_values() -> Result<()> {
+        let seeded = || MockInverter::new(&Config { mock_seed: Some(42), ..Config::default() });
+        let first = loads(&seeded(), 5).await?;
+        assert_eq!(first, loads(&seeded(), 5).await?);
+        // the values still change between polls
+        assert!(first.windows(2).any(|pair| pair[0] != pair[1]));
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+//! Plausibility bounds for the solar values
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::inverter::SolarData;
+
+/// what to do with a value outside of its bounds
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum PlausibilityAction {
+    /// set the value to the violated bound
+    Clamp,
+    /// discard the whole reading
+    Drop,
+    /// keep the last value of this field
+    Hold,
+}
+
+/// outcome of checking a reading against the bounds
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Verdict {
+    /// the reading is stored; contains a message for every value which was corrected
+    Accepted(Vec<String>),
+    /// the reading is discarded because of the value in the message, the last reading is kept
+    Dropped(String),
+}
+
+/// bounds for one field of `SolarData`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Bound {
+    field: String,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+/// fields which can have bounds
+const FIELDS: [&str; 11] = [
+    "old_inverter_power",
+    "new_inverter_power",
+    "both_inverter_power",
+    "battery_load_percentage",
+    "autonomy_percent",
+    "self_consumption_percent",
+    "self_sufficiency_percent",
+    "drain_from_battery",
+    "drain_from_grid",
+    "house_consumption",
+    "solar_coverage_percent",
+];
+
+/// parse bounds like `battery_load_percentage=0..100, both_inverter_power=..20000`
+pub(crate) fn parse_bounds(bounds: &str) -> Result<Vec<Bound>> {
+    bounds.split(',').map(str::trim).filter(|s| !s.is_empty()).map(|bound| {
+        let (field, range) = bound.split_once('=')
+            .ok_or_else(|| anyhow!("Plausibility bound {bound} should look like field=min..max"))?;
+        let field = field.trim();
+        if !FIELDS.contains(&field) {
+            bail!("Plausibility bound for unknown field {field}, known fields: {}", FIELDS.join(", "));
+        }
+        let (min, max) = range.split_once("..")
+            .ok_or_else(|| anyhow!("Plausibility bound {bound} should look like field=min..max"))?;
+        let parse = |value: &str| -> Result<Option<f64>> {
+            let value = value.trim();
+            if value.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some(value.parse::<f64>().map_err(|err| anyhow!("Plausibility bound {bound}: {err}"))?))
+        };
+        let (min, max) = (parse(min)?, parse(max)?);
+        if let (Some(min), Some(max)) = (min, max) {
+            if min > max {
+                bail!("Plausibility bound {bound} has min greater than max");
+            }
+        }
+        Ok(Bound { field: field.to_owned(), min, max })
+    }).collect()
+}
+
+/// get a field of `SolarData` by name
+#[allow(clippy::cast_precision_loss)]
+fn get(solar: &SolarData, field: &str) -> Option<f64> {
+    Some(match field {
+        "old_inverter_power" => f64::from(solar.old_inverter_power?),
+        "new_inverter_power" => f64::from(solar.new_inverter_power),
+        "both_inverter_power" => f64::from(solar.both_inverter_power?),
+        "battery_load_percentage" => f64::from(solar.battery_load_percentage),
+        "autonomy_percent" => f64::from(solar.autonomy_percent),
+        "self_consumption_percent" => f64::from(solar.self_consumption_percent),
+        "self_sufficiency_percent" => f64::from(solar.self_sufficiency_percent),
+        "drain_from_battery" => solar.drain_from_battery as f64,
+        "drain_from_grid" => solar.drain_from_grid as f64,
+        "house_consumption" => solar.house_consumption as f64,
+        "solar_coverage_percent" => f64::from(solar.solar_coverage_percent),
+        _ => return None,
+    })
+}
+
+/// set a field of `SolarData` by name
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn set(solar: &mut SolarData, field: &str, value: f64) {
+    match field {
+        "old_inverter_power" => solar.old_inverter_power = Some(value as u32),
+        "new_inverter_power" => solar.new_inverter_power = value as u32,
+        "both_inverter_power" => solar.both_inverter_power = Some(value as u32),
+        "battery_load_percentage" => solar.battery_load_percentage = value as u8,
+        "autonomy_percent" => solar.autonomy_percent = value as u8,
+        "self_consumption_percent" => solar.self_consumption_percent = value as u8,
+        "self_sufficiency_percent" => solar.self_sufficiency_percent = value as u8,
+        "drain_from_battery" => solar.drain_from_battery = value as i64,
+        "drain_from_grid" => solar.drain_from_grid = value as i64,
+        "house_consumption" => solar.house_consumption = value as u64,
+        "solar_coverage_percent" => solar.solar_coverage_percent = value as u8,
+        _ => {}
+    }
+}
+
+/// check a new reading against the bounds and apply the action to every violation
+pub(crate) fn check(
+    bounds: &[Bound],
+    action: PlausibilityAction,
+    solar: &mut SolarData,
+    previous: &SolarData,
+) -> Verdict {
+    let mut violations = Vec::new();
+    for bound in bounds {
+        let Some(value) = get(solar, &bound.field) else {
+            continue;
+        };
+        let limit = match (bound.min, bound.max) {
+            (Some(min), _) if value < min => min,
+            (_, Some(max)) if value > max => max,
+            _ => continue,
+        };
+        let message = format!("Implausible value for {}: {value}", bound.field);
+        warn!("{message}");
+        match action {
+            PlausibilityAction::Clamp => set(solar, &bound.field, limit),
+            PlausibilityAction::Drop => return Verdict::Dropped(message),
+            PlausibilityAction::Hold => {
+                if let Some(last) = get(previous, &bound.field) {
+                    set(solar, &bound.field, last);
+                }
+            }
+        }
+        violations.push(message);
+    }
+    Verdict::Accepted(violations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// reading with the given battery charge and house consumption
+    fn reading(battery: u8, consumption: u64) -> SolarData {
+        SolarData { battery_load_percentage: battery, house_consumption: consumption, ..SolarData::default() }
+    }
+
+    #[test]
+    fn drop_discards_the_reading() -> Result<()> {
+        let bounds = parse_bounds("battery_load_percentage=0..100, house_consumption=..30000")?;
+        let mut solar = reading(50, 90_000);
+        let verdict = check(&bounds, PlausibilityAction::Drop, &mut solar, &reading(40, 500));
+        assert_eq!(verdict, Verdict::Dropped("Implausible value for house_consumption: 90000".to_owned()));
+        Ok(())
+    }
+
+    #[test]
+    fn drop_accepts_plausible_readings() -> Result<()> {
+        let bounds = parse_bounds("battery_load_percentage=0..100, house_consumption=..30000")?;
+        let mut solar = reading(50, 800);
+        assert_eq!(check(&bounds, PlausibilityAction::Drop, &mut solar, &reading(40, 500)), Verdict::Accepted(vec![]));
+        Ok(())
+    }
+
+    #[test]
+    fn clamp_and_hold_correct_the_value() -> Result<()> {
+        let bounds = parse_bounds("house_consumption=100..30000")?;
+        let mut clamped = reading(50, 90_000);
+        let verdict = check(&bounds, PlausibilityAction::Clamp, &mut clamped, &reading(40, 500));
+        assert_eq!(verdict, Verdict::Accepted(vec!["Implausible value for house_consumption: 90000".to_owned()]));
+        assert_eq!(clamped.house_consumption, 30_000);
+
+        let mut held = reading(50, 20);
+        let hold_verdict = check(&bounds, PlausibilityAction::Hold, &mut held, &reading(40, 500));
+        assert_eq!(hold_verdict, Verdict::Accepted(vec!["Implausible value for house_consumption: 20".to_owned()]));
+        assert_eq!(held.house_consumption, 500);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_bounds_rejects_invalid_bounds() {
+        assert!(parse_bounds("unknown=0..1").is_err());
+        assert!(parse_bounds("house_consumption=10").is_err());
+        assert!(parse_bounds("house_consumption=10..1").is_err());
+        assert!(parse_bounds("house_consumption=a..").is_err());
+    }
+}
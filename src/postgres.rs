@@ -0,0 +1,93 @@
+//! Writing data points to postgres / timescaledb
+
+use anyhow::{anyhow, Result};
+use futures_util::future::BoxFuture;
+use poem_openapi::types::ToJSON;
+use serde_json::Value;
+use tokio::spawn;
+use tokio::sync::Mutex;
+use tokio_postgres::{Client, NoTls};
+use tracing::{error, info};
+
+use crate::config::Config;
+use crate::sink::{Point, Precision, Sink};
+
+/// writes points as rows to postgres, reconnecting when the connection was lost
+pub(crate) struct PostgresSink {
+    url: String,
+    table: String,
+    create_table: bool,
+    precision: Precision,
+    client: Mutex<Option<Client>>,
+}
+
+impl PostgresSink {
+    pub(crate) fn new(config: &Config) -> Option<Self> {
+        config.postgres_url.clone().map(|url| PostgresSink {
+            url,
+            table: config.postgres_table.clone(),
+            create_table: config.postgres_create_table,
+            precision: config.postgres_precision,
+            client: Mutex::default(),
+        })
+    }
+
+    /// quoted table name, safe to use in statements
+    fn table(&self) -> String {
+        format!("\"{}\"", self.table.replace('"', "\"\""))
+    }
+
+    async fn connect(&self) -> Result<Client> {
+        let (client, connection) = tokio_postgres::connect(&self.url, NoTls).await?;
+        spawn(async move {
+            if let Err(err) = connection.await {
+                error!("Postgres connection Error: {err}");
+            }
+        });
+        if self.create_table {
+            client.batch_execute(&format!(
+                "CREATE TABLE IF NOT EXISTS {} (time TIMESTAMPTZ NOT NULL, solar JSONB NOT NULL, wattpilot JSONB NOT NULL)",
+                self.table()
+            )).await?;
+        }
+        info!("Connected to postgres");
+        Ok(client)
+    }
+}
+
+impl Sink for PostgresSink {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn write<'a>(&'a self, point: &'a Point) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut lock = self.client.lock().await;
+            if lock.as_ref().is_none_or(Client::is_closed) {
+                *lock = Some(self.connect().await?);
+            }
+            let Some(client) = lock.as_ref() else {
+                return Err(anyhow!("No postgres connection"));
+            };
+            let solar = point.solar.to_json().unwrap_or_default();
+            // with more than one wattpilot, their data is keyed by id
+            let wattpilot = if point.wattpilots.len() > 1 {
+                Value::Object(point.wattpilots.iter()
+                    .map(|(id, data)| (id.clone(), data.to_json().unwrap_or_default()))
+                    .collect())
+            } else {
+                point.wattpilot.to_json().unwrap_or_default()
+            };
+            if let Err(err) = client.execute(
+                &format!("INSERT INTO {} (time, solar, wattpilot) VALUES ($1, $2, $3)", self.table()),
+                &[&self.precision.truncate(point.time), &solar, &wattpilot],
+            ).await {
+                error!("Postgres insert Error: {err}");
+                // reconnect on next write
+                *lock = None;
+                return Err(anyhow!(err));
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,167 @@
+//! rate limiting of the api per client ip
+
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use linked_hash_map::LinkedHashMap;
+use poem::http::StatusCode;
+use poem::http::header::RETRY_AFTER;
+use poem::{Endpoint, IntoResponse, Request, Response, Result};
+use serde_json::json;
+
+/// number of clients with a bucket; the least recently seen client is dropped for a new one
+const MAX_CLIENTS: usize = 4096;
+
+/// tokens of one client; one token is taken per request
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// token buckets of all clients, refilled at `rate_limit_per_minute` tokens per minute
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    per_minute: u32,
+    exempt_path: String,
+    /// buckets by client, least recently seen first
+    buckets: Mutex<LinkedHashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    /// limiter for `per_minute` requests per client; 0 disables it\
+    /// requests to `exempt_path` are never limited
+    pub(crate) fn new(per_minute: u32, exempt_path: String) -> Self {
+        RateLimiter { per_minute, exempt_path, buckets: Mutex::new(LinkedHashMap::new()) }
+    }
+
+    /// take a token of the client; the seconds until the next token if there is none left
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn take(&self, ip: IpAddr) -> Option<u64> {
+        let capacity = f64::from(self.per_minute);
+        let per_second = capacity / 60.0;
+        let now = Instant::now();
+        // a poisoned lock only means another request panicked, the buckets are still usable
+        let mut buckets = self.buckets.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let client = client(ip);
+        if buckets.get_refresh(&client).is_none() && buckets.len() >= MAX_CLIENTS {
+            buckets.pop_front();
+        }
+        let bucket = buckets.entry(client).or_insert(Bucket { tokens: capacity, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return None;
+        }
+        Some(((1.0 - bucket.tokens) / per_second).ceil().max(1.0) as u64)
+    }
+}
+
+/// key of the bucket of an ip; ipv6 clients by their /64 network, as one host may use many of its addresses
+fn client(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & !u128::from(u64::MAX))),
+        },
+    }
+}
+
+/// reject requests of clients which exceeded their rate with 429 before passing them on;\
+/// clients are told by their ip, so all requests forwarded by a reverse proxy share one bucket
+pub(crate) async fn limit_rate<E: Endpoint>(next: Arc<E>, req: Request, limiter: Arc<RateLimiter>) -> Result<Response> {
+    let ip = req.remote_addr().as_socket_addr().map(std::net::SocketAddr::ip);
+    let exceeded = match ip {
+        Some(ip) if limiter.per_minute > 0 && req.uri().path() != limiter.exempt_path => limiter.take(ip),
+        _ => None,
+    };
+    let Some(retry_after) = exceeded else {
+        return next.call(req).await.map(IntoResponse::into_response);
+    };
+    Ok(Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(RETRY_AFTER, retry_after)
+        .content_type("application/json")
+        .body(json!({"error": "Too many requests"}).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::listener::{Acceptor, Listener, TcpListener};
+    use poem::{EndpointExt, Route, Server, get, handler};
+    use reqwest::StatusCode as ClientStatus;
+
+    use super::*;
+
+    #[handler]
+    fn ok() -> &'static str {
+        "ok"
+    }
+
+    /// start a server limited to `per_minute` requests, with `/api/health` exempt; returns its url
+    async fn server(per_minute: u32) -> anyhow::Result<String> {
+        let limiter = Arc::new(RateLimiter::new(per_minute, "/api/health".to_owned()));
+        let route = Route::new()
+            .at("/api/solar", get(ok))
+            .at("/api/health", get(ok))
+            .around(move |next, req| limit_rate(next, req, limiter.clone()));
+        let acceptor = TcpListener::bind("127.0.0.1:0").into_acceptor().await?;
+        let addr = acceptor.local_addr().first().and_then(|addr| addr.as_socket_addr().copied())
+            .ok_or_else(|| anyhow::anyhow!("Server has no address"))?;
+        tokio::spawn(Server::new_with_acceptor(acceptor).run(route));
+        Ok(format!("http://{addr}"))
+    }
+
+    #[tokio::test]
+    async fn requests_above_the_limit_are_rejected() -> anyhow::Result<()> {
+        let url = server(2).await?;
+        let client = reqwest::Client::new();
+        for _ in 0..2 {
+            assert_eq!(client.get(format!("{url}/api/solar")).send().await?.status(), ClientStatus::OK);
+        }
+        let rejected = client.get(format!("{url}/api/solar")).send().await?;
+        assert_eq!(rejected.status(), ClientStatus::TOO_MANY_REQUESTS);
+        // one token every 30 seconds with 2 requests per minute
+        assert_eq!(rejected.headers().get(RETRY_AFTER.as_str()).map(reqwest::header::HeaderValue::to_str).transpose()?, Some("30"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn health_is_never_limited() -> anyhow::Result<()> {
+        let url = server(1).await?;
+        let client = reqwest::Client::new();
+        for _ in 0..5 {
+            assert_eq!(client.get(format!("{url}/api/health")).send().await?.status(), ClientStatus::OK);
+        }
+        assert_eq!(client.get(format!("{url}/api/solar")).send().await?.status(), ClientStatus::OK);
+        assert_eq!(client.get(format!("{url}/api/solar")).send().await?.status(), ClientStatus::TOO_MANY_REQUESTS);
+        Ok(())
+    }
+
+    #[test]
+    fn ipv6_clients_share_their_network() -> anyhow::Result<()> {
+        let limiter = RateLimiter::new(1, String::new());
+        assert_eq!(limiter.take("2001:db8::1".parse()?), None);
+        assert!(limiter.take("2001:db8::2".parse()?).is_some());
+        assert_eq!(limiter.take("2001:db8:0:1::1".parse()?), None);
+        assert_eq!(client("::ffff:192.0.2.1".parse()?), "192.0.2.1".parse::<IpAddr>()?);
+        Ok(())
+    }
+
+    #[test]
+    fn least_recently_seen_client_is_dropped() -> anyhow::Result<()> {
+        let limiter = RateLimiter::new(1, String::new());
+        let first: IpAddr = "192.0.2.1".parse()?;
+        assert_eq!(limiter.take(first), None);
+        for index in 0..MAX_CLIENTS {
+            limiter.take(IpAddr::from(u32::try_from(index)?.to_be_bytes()));
+        }
+        assert!(limiter.buckets.lock().is_ok_and(|buckets| buckets.len() == MAX_CLIENTS));
+        // the first client was dropped, so it starts with a full bucket again
+        assert_eq!(limiter.take(first), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+//! Destinations the collected data points are written to
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::info;
+
+use crate::config::Config;
+use crate::influx::InfluxSink;
+use crate::inverter::SolarData;
+use crate::postgres::PostgresSink;
+use crate::wattpilot::WattpilotData;
+
+/// one data point, as written to every sink
+#[derive(Debug, Clone)]
+pub(crate) struct Point {
+    /// time the point was taken
+    pub(crate) time: OffsetDateTime,
+    /// data of rest of system
+    pub(crate) solar: SolarData,
+    /// data of the first connected wattpilot
+    pub(crate) wattpilot: WattpilotData,
+    /// data of all connected wattpilots by id
+    pub(crate) wattpilots: Vec<(String, WattpilotData)>,
+}
+
+/// precision of the timestamps written by a sink
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Precision {
+    /// seconds
+    S,
+    /// milliseconds
+    Ms,
+    /// microseconds
+    Us,
+    /// nanoseconds
+    Ns,
+}
+
+impl Precision {
+    /// name of this precision, as used in the config and by influx
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Precision::S => "s",
+            Precision::Ms => "ms",
+            Precision::Us => "us",
+            Precision::Ns => "ns",
+        }
+    }
+
+    /// nanoseconds per unit of this precision
+    fn nanos(self) -> i128 {
+        match self {
+            Precision::S => 1_000_000_000,
+            Precision::Ms => 1_000_000,
+            Precision::Us => 1_000,
+            Precision::Ns => 1,
+        }
+    }
+
+    /// unix timestamp in units of this precision
+    pub(crate) fn timestamp(self, time: OffsetDateTime) -> i128 {
+        time.unix_timestamp_nanos() / self.nanos()
+    }
+
+    /// cut off everything below this precision
+    pub(crate) fn truncate(self, time: OffsetDateTime) -> OffsetDateTime {
+        let nanos = self.timestamp(time) * self.nanos();
+        OffsetDateTime::from_unix_timestamp_nanos(nanos).unwrap_or(time)
+    }
+}
+
+/// a destination for data points
+pub(crate) trait Sink: Send + Sync {
+    /// name of the sink, used for logging and monitoring
+    fn name(&self) -> &'static str;
+
+    /// write one data point
+    fn write<'a>(&'a self, point: &'a Point) -> BoxFuture<'a, Result<()>>;
+
+    /// write everything which was held back, e.g. on shutdown
+    fn flush(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// number of lines held back, e.g. because writing failed
+    fn pending(&self) -> usize {
+        0
+    }
+}
+
+/// create all configured sinks
+pub(crate) fn sinks(config: &Config, client: &reqwest::Client) -> Vec<Arc<dyn Sink>> {
+    let mut sinks: Vec<Arc<dyn Sink>> = vec![Arc::new(InfluxSink::new(config, client))];
+    if let Some(postgres) = PostgresSink::new(config) {
+        info!("Postgres sink activated");
+        sinks.push(Arc::new(postgres));
+    }
+    sinks
+}
@@ -0,0 +1,147 @@
+//! Solaredge inverters, queried via the monitoring api
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::config::Config;
+use crate::inverter::{Inverter, SolarData, self_sufficiency, solar_coverage};
+use crate::utils::deserialize_null_default;
+
+#[derive(Deserialize, Debug, Default)]
+struct Node {
+    /// current power of this node; unit of the power flow
+    #[serde(rename = "currentPower", default, deserialize_with = "deserialize_null_default")]
+    current_power: f64,
+    /// current charge of the battery, only reported for the storage; data in percent
+    #[serde(rename = "chargeLevel", default, deserialize_with = "deserialize_null_default")]
+    charge_level: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Connection {
+    from: String,
+    to: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct PowerFlow {
+    /// unit of all powers, `W`, `kW` or `MW`
+    #[serde(default)]
+    unit: String,
+    /// directions of the power flows between the nodes
+    #[serde(default)]
+    connections: Vec<Connection>,
+    #[serde(rename = "GRID", default)]
+    grid: Node,
+    #[serde(rename = "LOAD", default)]
+    load: Node,
+    #[serde(rename = "PV", default)]
+    pv: Node,
+    #[serde(rename = "STORAGE", default)]
+    storage: Node,
+}
+
+#[derive(Deserialize, Debug)]
+struct PowerFlowJson {
+    #[serde(rename = "siteCurrentPowerFlow")]
+    site_current_power_flow: PowerFlow,
+}
+
+impl PowerFlow {
+    /// whether power flows from one node to the other
+    fn flows(&self, from: &str, to: &str) -> bool {
+        self.connections.iter()
+            .any(|c| c.from.eq_ignore_ascii_case(from) && c.to.eq_ignore_ascii_case(to))
+    }
+
+    /// factor to convert the powers to watts
+    fn factor(&self) -> f64 {
+        match self.unit.as_str() {
+            "W" => 1.0,
+            "MW" => 1_000_000.0,
+            _ => 1000.0,
+        }
+    }
+}
+
+/// solaredge inverter, queried via `/site/{site_id}/currentPowerFlow` of the monitoring api
+pub(crate) struct SolarEdgeInverter;
+
+impl Inverter for SolarEdgeInverter {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    async fn fetch(&self, config: &Config, client: &reqwest::Client, url: &Url) -> Result<(SolarData, String)> {
+        let site_id = config.solaredge_site_id.as_deref().ok_or_else(|| anyhow!("SolarEdge site id is not set"))?;
+        let mut url = url.join(&format!("/site/{site_id}/currentPowerFlow"))?;
+        url.query_pairs_mut().append_pair("api_key", config.solaredge_api_key.as_deref().unwrap_or_default());
+        // the url contains the api key, so it is removed from all errors
+        let resp = client.get(url).timeout(Duration::from_secs(config.inverter_timeout_secs)).send().await
+            .map_err(reqwest::Error::without_url)?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            return Err(anyhow!("Response Error: {status}, {}", resp.text().await.map_err(reqwest::Error::without_url)?));
+        }
+        let raw = resp.text().await.map_err(reqwest::Error::without_url)?;
+        let flow = serde_json::from_str::<PowerFlowJson>(raw.as_str())?.site_current_power_flow;
+        let factor = flow.factor();
+        let pv = flow.pv.current_power * factor;
+        let load = flow.load.current_power * factor;
+        // grid: positive = import, battery: positive = discharge
+        let grid = if flow.flows("load", "grid") { -1.0 } else { 1.0 } * flow.grid.current_power * factor;
+        let battery = if flow.flows("storage", "load") { 1.0 } else { -1.0 } * flow.storage.current_power * factor;
+        let omit_secondary = config.omit_missing_secondary_meter;
+        let self_consumption = if pv <= 0.0 { 0.0 } else { ((pv + grid.min(0.0)) / pv * 100.0).clamp(0.0, 100.0) };
+        Ok((SolarData {
+            last_time: OffsetDateTime::now_utc(),
+            old_inverter_power: (!omit_secondary).then_some(0),
+            new_inverter_power: pv as u32,
+            both_inverter_power: (!omit_secondary).then_some(pv as u32),
+            battery_load_percentage: flow.storage.charge_level as u8,
+            autonomy_percent: self_sufficiency(load, grid),
+            self_consumption_percent: self_consumption.round() as u8,
+            self_sufficiency_percent: self_sufficiency(load, grid),
+            drain_from_battery: battery as i64,
+            drain_from_grid: grid as i64,
+            house_consumption: load as u64,
+            grid_import_watts: grid.max(0.0) as u64,
+            grid_export_watts: (-grid).max(0.0) as u64,
+            solar_coverage_percent: solar_coverage(load, pv),
+            inverter_clock_skew: None,
+            ev_charging_power: None,
+            grid_phases: None,
+            energy_day: None,
+            energy_year: None,
+            energy_total: None,
+            battery_temperature: None,
+            inverter_status_code: None,
+            inverter_error_code: None,
+        }, raw))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn errors_do_not_contain_the_api_key() -> Result<()> {
+        // nothing listens on the port of the dropped listener
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = Url::parse(&format!("http://{}", listener.local_addr()?))?;
+        drop(listener);
+        let config = Config {
+            solaredge_site_id: Some("12345".to_owned()),
+            solaredge_api_key: Some("topsecretkey".to_owned()),
+            ..Config::default()
+        };
+        let result = SolarEdgeInverter.fetch(&config, &reqwest::Client::new(), &url).await;
+        let err = result.err().ok_or_else(|| anyhow!("Request to a closed port succeeded"))?;
+        assert!(!format!("{err:?} {err:#}").contains("topsecretkey"));
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+//! statistics over the polled solar values
+
+use std::collections::VecDeque;
+
+use chrono::Utc;
+use chrono_tz::Tz;
+use poem_openapi::Object;
+use time::OffsetDateTime;
+
+use crate::config::Config;
+use crate::inverter::SolarData;
+
+/// extrema of the solar values of the current day; the day ends at midnight in the configured `timezone`
+#[derive(Object, Debug, Clone, Default)]
+pub struct DailyStats {
+    /// day of the values, e.g. `2024-06-01`; empty before the first poll
+    pub(crate) date: String,
+    /// highest power produced by both pv systems today; data in watts
+    pub(crate) peak_power: Option<u32>,
+    /// time of the highest power
+    pub(crate) peak_power_time: Option<OffsetDateTime>,
+    /// lowest charge of the battery today; data in percent
+    pub(crate) min_battery_percentage: Option<u8>,
+    /// time of the lowest charge
+    pub(crate) min_battery_time: Option<OffsetDateTime>,
+}
+
+impl DailyStats {
+    /// include the values of a poll, starting over on a new day
+    pub(crate) fn update(&mut self, solar: &SolarData, timezone: Tz) {
+        let date = Utc::now().with_timezone(&timezone).date_naive().to_string();
+        if self.date != date {
+            *self = DailyStats { date, ..DailyStats::default() };
+        }
+        let power = solar.both_inverter_power.unwrap_or(solar.new_inverter_power);
+        if self.peak_power.is_none_or(|peak| power > peak) {
+            self.peak_power = Some(power);
+            self.peak_power_time = Some(solar.last_time);
+        }
+        let battery = solar.battery_load_percentage;
+        if self.min_battery_percentage.is_none_or(|min| battery < min) {
+            self.min_battery_percentage = Some(battery);
+            self.min_battery_time = Some(solar.last_time);
+        }
+    }
+}
+
+/// longest window of the rolling averages; data in seconds
+const LONGEST_WINDOW_SECS: u64 = 15 * 60;
+
+/// averages of the polls within a window
+#[derive(Object, Debug, Clone)]
+pub struct WindowAverages {
+    /// number of polls within the window
+    pub(crate) samples: usize,
+    /// power produced by both pv systems; data in watts
+    pub(crate) both_inverter_power: f64,
+    /// power consumed by the house; data in watts
+    pub(crate) house_consumption: f64,
+    /// power drawn from the grid; data in watts
+    pub(crate) drain_from_grid: f64,
+}
+
+/// moving averages of the last polls; not set if there was no poll within the window
+#[derive(Object, Debug, Clone)]
+pub struct RollingAverages {
+    /// over the last minute
+    pub(crate) one_minute: Option<WindowAverages>,
+    /// over the last 5 minutes
+    pub(crate) five_minutes: Option<WindowAverages>,
+    /// over the last 15 minutes
+    pub(crate) fifteen_minutes: Option<WindowAverages>,
+}
+
+/// values of one poll
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    time: OffsetDateTime,
+    both_inverter_power: f64,
+    house_consumption: f64,
+    drain_from_grid: f64,
+}
+
+/// the polls of the longest window, oldest first
+#[derive(Debug)]
+pub(crate) struct RollingSamples {
+    samples: VecDeque<Sample>,
+    capacity: usize,
+}
+
+impl RollingSamples {
+    /// keep enough polls for the longest window at the configured interval
+    pub(crate) fn new(config: &Config) -> Self {
+        let capacity = usize::try_from(LONGEST_WINDOW_SECS.div_ceil(config.poll_interval_secs.max(1)))
+            .unwrap_or(usize::MAX)
+            .max(1);
+        RollingSamples { samples: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    /// add the values of a poll, dropping the oldest one if the buffer is full
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn push(&mut self, solar: &SolarData) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            time: solar.last_time,
+            both_inverter_power: f64::from(solar.both_inverter_power.unwrap_or(solar.new_inverter_power)),
+            house_consumption: solar.house_consumption as f64,
+            drain_from_grid: solar.drain_from_grid as f64,
+        });
+    }
+
+    /// averages of the polls within the last `secs` seconds
+    #[allow(clippy::cast_precision_loss)]
+    fn window(&self, now: OffsetDateTime, secs: i64) -> Option<WindowAverages> {
+        let start = now - time::Duration::seconds(secs);
+        let samples: Vec<&Sample> = self.samples.iter().filter(|sample| sample.time > start).collect();
+        if samples.is_empty() {
+            return None;
+        }
+        let count = samples.len() as f64;
+        let average = |value: fn(&Sample) -> f64| samples.iter().map(|sample| value(sample)).sum::<f64>() / count;
+        Some(WindowAverages {
+            samples: samples.len(),
+            both_inverter_power: average(|sample| sample.both_inverter_power),
+            house_consumption: average(|sample| sample.house_consumption),
+            drain_from_grid: average(|sample| sample.drain_from_grid),
+        })
+    }
+
+    /// averages over the last 1, 5 and 15 minutes
+    pub(crate) fn averages(&self) -> RollingAverages {
+        let now = OffsetDateTime::now_utc();
+        RollingAverages {
+            one_minute: self.window(now, 60),
+            five_minutes: self.window(now, 5 * 60),
+            fifteen_minutes: self.window(now, 15 * 60),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn day_follows_the_timezone() {
+        // 26 hours apart, so the dates always differ
+        let mut ahead = DailyStats::default();
+        ahead.update(&SolarData::default(), Tz::Pacific__Kiritimati);
+        let mut behind = DailyStats::default();
+        behind.update(&SolarData::default(), Tz::Etc__GMTPlus12);
+        assert_ne!(ahead.date, behind.date);
+        assert_eq!(ahead.date, Utc::now().with_timezone(&Tz::Pacific__Kiritimati).date_naive().to_string());
+    }
+
+    #[test]
+    fn timezone_from_config() -> anyhow::Result<()> {
+        let config: Config = serde_json::from_str(r#"{"timezone": "Europe/Berlin"}"#)?;
+        assert_eq!(config.timezone, Tz::Europe__Berlin);
+        assert!(serde_json::from_str::<Config>(r#"{"timezone": "Mars/Olympus"}"#).is_err());
+        Ok(())
+    }
+}
@@ -1,14 +1,21 @@
 use std::env;
-use std::sync::Arc;
-use poem::http::header::AUTHORIZATION;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use futures_util::future::join_all;
 use serde::{Deserialize, Deserializer};
 use time::OffsetDateTime;
-use tokio::sync::RwLock;
-use tracing::{error, info};
+use tokio::join;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{debug, error, info};
 use tracing::log::warn;
-use crate::config::Config;
+use url::Url;
+use crate::AppState;
+use crate::config::{Config, redact_secrets};
 use crate::inverter::{fetch_solar_values, SolarData};
-use crate::wattpilot::{Wattpilot, WattpilotData};
+use crate::plausibility::Verdict;
+use crate::sink::Point;
+use crate::wattpilot::WattpilotData;
 
 pub(crate) fn deserialize_null_default<'de, D, T>(deserializer: D) -> poem::Result<T, D::Error>
     where
@@ -19,121 +26,343 @@ pub(crate) fn deserialize_null_default<'de, D, T>(deserializer: D) -> poem::Resu
     Ok(opt.unwrap_or_default())
 }
 
-async fn contact_monitoring(config: &Config, code: u32, body: Option<String>) {
-    let client2 = reqwest::Client::new();
+/// circuit breaker for the monitoring endpoint
+#[derive(Debug, Default)]
+pub(crate) struct MonitoringBreaker {
+    /// how many pings failed in a row
+    failures: u32,
+    /// no pings are sent until this time
+    open_until: Option<OffsetDateTime>,
+}
+
+/// subsystem a monitoring ping belongs to
+#[derive(Debug, Clone, Copy)]
+enum Check {
+    /// fetching the solar values
+    Main,
+    /// writing data to influx and the other sinks
+    Influx,
+    /// wattpilot data
+    Wattpilot,
+}
+
+impl Check {
+    /// healthcheck url for this subsystem, falling back to the main one
+    fn url(self, config: &Config) -> Option<Url> {
+        match self {
+            Check::Main => None,
+            Check::Influx => config.healthcheck_url_influx.clone(),
+            Check::Wattpilot => config.healthcheck_url_wattpilot.clone(),
+        }.or_else(|| config.healthcheck_url.clone())
+    }
+}
+
+/// event reported to the monitoring
+#[derive(Debug, Clone, Copy)]
+enum MonitorEvent {
+    /// a poll starts; always sent as `start`
+    Start,
+    /// the data was fetched and written
+    Ok,
+    /// the solar values could not be fetched
+    FetchFailed,
+    /// data is too old or implausible
+    Stale,
+    /// the data could not be written to a sink
+    WriteFailed,
+}
+
+impl MonitorEvent {
+    /// path segment appended to the healthcheck url for this event; empty = none
+    fn segment(self, config: &Config) -> &str {
+        match self {
+            MonitorEvent::Start => "start",
+            MonitorEvent::Ok => &config.monitoring_ok_path,
+            MonitorEvent::FetchFailed => &config.monitoring_fetch_failed_path,
+            MonitorEvent::Stale => &config.monitoring_stale_path,
+            MonitorEvent::WriteFailed => &config.monitoring_write_failed_path,
+        }
+    }
+}
+
+async fn contact_monitoring(state: &AppState, check: Check, event: MonitorEvent, body: Option<String>) {
+    let config = &state.config;
+    let Some(mut url) = check.url(config) else {
+        debug!("No healthcheck url set, not contacting monitoring");
+        return;
+    };
+    if config.monitoring_failure_threshold > 0 {
+        if let Some(open_until) = state.monitoring_breaker.read().await.open_until {
+            if OffsetDateTime::now_utc() < open_until {
+                debug!("Monitoring circuit breaker open until {open_until}, not contacting monitoring");
+                return;
+            }
+            info!("Monitoring circuit breaker half open, probing monitoring");
+        }
+    }
 
-    // config will have this field checked at this time
-    #[allow(clippy::unwrap_used)]
-        let mut url = config.healthcheck_url.clone().unwrap();
-    #[allow(clippy::unwrap_used)]
-    url.path_segments_mut().unwrap().push(code.to_string().as_str());
-    if let Err(err) = match body {
+    let segment = event.segment(config);
+    if !segment.is_empty() {
+        #[allow(clippy::unwrap_used)]
+        url.path_segments_mut().unwrap().push(segment);
+    }
+    let result = match body {
         None => {
-            client2
+            state.client
                 .post(url)
                 .send()
                 .await
         }
         Some(b) => {
-            client2
+            // the body is shown in the monitoring, errors must not leak keys or passwords there
+            state.client
                 .post(url)
-                .body(b)
+                .body(redact_secrets(config, &b))
                 .send()
                 .await
         }
-    } {
-        error!("Error while contacting monitoring: {err}");
+    }.and_then(reqwest::Response::error_for_status);
+    if config.monitoring_failure_threshold == 0 {
+        if let Err(err) = result {
+            error!("Error while contacting monitoring: {}", err.without_url());
+        }
+        return;
+    }
+    let mut breaker = state.monitoring_breaker.write().await;
+    match result {
+        Ok(_) => {
+            if breaker.open_until.is_some() {
+                info!("Monitoring reachable again, closing circuit breaker");
+            }
+            *breaker = MonitoringBreaker::default();
+        }
+        Err(err) => {
+            error!("Error while contacting monitoring: {}", err.without_url());
+            breaker.failures += 1;
+            if breaker.failures >= config.monitoring_failure_threshold {
+                let cooldown = Duration::from_secs(config.monitoring_cooldown_secs);
+                breaker.open_until = Some(OffsetDateTime::now_utc() + cooldown);
+                warn!(
+                    "Monitoring failed {} times in a row, opening circuit breaker for {}s",
+                    breaker.failures,
+                    config.monitoring_cooldown_secs
+                );
+            }
+        }
+    }
+}
+
+/// time until the next poll; aligned polls land on multiples of the interval
+pub(crate) fn poll_wait(config: &Config) -> Duration {
+    let interval = Duration::from_secs(config.poll_interval_secs);
+    if !config.poll_align {
+        return interval;
+    }
+    let interval_nanos = i128::from(config.poll_interval_secs) * 1_000_000_000;
+    let elapsed = OffsetDateTime::now_utc().unix_timestamp_nanos().rem_euclid(interval_nanos);
+    // elapsed is smaller than the interval
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    interval.saturating_sub(Duration::from_nanos(elapsed as u64))
+}
+
+/// point and monitoring pings, handed from the poll loop to the writer task
+pub(crate) struct PendingPoint {
+    point: Point,
+    /// pings which are sent to the monitoring, independent of the write result
+    pings: Vec<(Check, MonitorEvent, Option<String>)>,
+}
+
+/// current data of all wattpilots; data which is too old is replaced by the defaults\
+/// returns a message for every wattpilot with too old data
+async fn wattpilot_values(state: &AppState) -> (Vec<(String, WattpilotData)>, Vec<String>) {
+    let mut wattpilots = Vec::with_capacity(state.wattpilots.len());
+    let mut too_old = Vec::new();
+    for wp in state.wattpilots.iter() {
+        let read = wp.read().await;
+        let data = read.data.read().await;
+        let wp_age = (OffsetDateTime::now_utc() - data.last_updated).as_seconds_f64();
+        if !read.authenticated || !data.is_fresh(&state.config) {
+            warn!("Wattpilot {} data too old: {wp_age}", read.id);
+            too_old.push(format!("Wattpilot {} data too old: {wp_age}", read.id));
+            wattpilots.push((read.id.clone(), WattpilotData::default()));
+        } else {
+            wattpilots.push((read.id.clone(), data.clone()));
+        }
     }
+    (wattpilots, too_old)
 }
 
-/// add point to database
-pub(crate) async fn add_point(
-    config: &Config,
-    solar_data: &Arc<RwLock<SolarData>>,
-    wp_arc: &Option<Arc<RwLock<Wattpilot>>>
-) {
+/// the point which is written to the sinks, from the solar values and the data of all wattpilots
+fn build_point(time: OffsetDateTime, solar: SolarData, wattpilots: Vec<(String, WattpilotData)>) -> Point {
+    let wattpilot = wattpilots.first().map(|(_, data)| data.clone()).unwrap_or_default();
+    Point { time, solar, wattpilot, wattpilots }
+}
+
+/// the point which would be written for the current values, without fetching or writing anything
+pub(crate) async fn current_point(state: &AppState) -> Point {
+    let solar = state.solar_data.read().await.clone();
+    let (wattpilots, _) = wattpilot_values(state).await;
+    build_point(OffsetDateTime::now_utc(), solar, wattpilots)
+}
+
+/// add point to database; the point is written by the writer task
+pub(crate) async fn add_point(state: &AppState, writer: &Sender<PendingPoint>) {
+    let config = &state.config;
+    let solar_data = &state.solar_data;
     let actual_time = OffsetDateTime::now_utc();
-    if !fetch_solar_values(config, solar_data.clone()).await {
-        contact_monitoring(config, 1, Some("Solar values could not be fetched".to_owned())).await;
-        return;
+    if config.monitoring_send_start {
+        contact_monitoring(state, Check::Main, MonitorEvent::Start, None).await;
     }
+    let violations = match fetch_solar_values(state).await {
+        Ok(Verdict::Accepted(violations)) => violations,
+        Ok(Verdict::Dropped(message)) => {
+            // the inverter answered, so this is no fetch failure; the last values are kept
+            info!("Dropped implausible reading, not adding point to database");
+            if config.plausibility_monitoring {
+                contact_monitoring(state, Check::Main, MonitorEvent::Stale, Some(message)).await;
+            }
+            return;
+        }
+        Err(err) => {
+            state.inverter_failures.fetch_add(1, Ordering::Relaxed);
+            let body = format!("Solar values could not be fetched: {err}");
+            contact_monitoring(state, Check::Main, MonitorEvent::FetchFailed, Some(body)).await;
+            return;
+        }
+    };
+    let polled = solar_data.read().await.clone();
+    state.daily_stats.write().await.update(&polled, config.timezone);
+    state.rolling_samples.write().await.push(&polled);
+    // nobody listening is fine
+    let _ = state.updates.send(());
     if env::var("NO_DB").is_ok() {
-        contact_monitoring(config, 0, None).await;
+        contact_monitoring(state, Check::Main, MonitorEvent::Ok, None).await;
         return;
     }
+    let startup_wait = Duration::from_secs(config.wattpilot_startup_wait_secs);
+    if actual_time - state.started < startup_wait {
+        for wp in state.wattpilots.iter() {
+            if !wp.read().await.authenticated {
+                info!("Wattpilot not authenticated yet, not adding point to database");
+                return;
+            }
+        }
+    }
     info!("Adding point to database {}", actual_time);
-    let solar = solar_data.read().await;
+    let solar = solar_data.read().await.clone();
+    // pings which are sent to the monitoring, independent of the write result
+    let mut pings = Vec::new();
     let solar_age = (OffsetDateTime::now_utc() - solar.last_time).as_seconds_f64();
-    if solar_age > 30f64 {
+    if !solar.is_fresh(config) {
         warn!("Solar data too old: {solar_age}");
-        contact_monitoring(config, 2, Some(format!("Solar data too old: {solar_age}").to_owned())).await;
-    }
-    let wp = match wp_arc {
-        None => WattpilotData::default(),
-        Some(some) => {
-            let read = some.read().await;
-            let wp_age = (OffsetDateTime::now_utc() - read.data.read().await.last_updated).as_seconds_f64();
-            if !read.authenticated || wp_age > 30f64  {
-                warn!("Wattpilot data too old: {wp_age}");
-                contact_monitoring(config, 2, Some(format!("Wattpilot data too old: {wp_age}").to_owned())).await;
-                WattpilotData::default()
-            } else {
-                read.data.read().await.clone()
-            }
+        pings.push((Check::Main, MonitorEvent::Stale, Some(format!("Solar data too old: {solar_age}"))));
+    } else if config.plausibility_monitoring && !violations.is_empty() {
+        pings.push((Check::Main, MonitorEvent::Stale, Some(violations.join("\n"))));
+    } else if config.healthcheck_url_influx.is_some() {
+        // the write result goes to its own check
+        pings.push((Check::Main, MonitorEvent::Ok, None));
+    }
+    let (wattpilots, too_old) = wattpilot_values(state).await;
+    if !too_old.is_empty() {
+        pings.push((Check::Wattpilot, MonitorEvent::Stale, Some(too_old.join("\n"))));
+    } else if !wattpilots.is_empty() && config.healthcheck_url_wattpilot.is_some() {
+        pings.push((Check::Wattpilot, MonitorEvent::Ok, None));
+    }
+    let point = build_point(actual_time, solar, wattpilots);
+    // never block the poll loop on a slow writer
+    if let Err(err) = writer.try_send(PendingPoint { point, pings }) {
+        let reason = match err {
+            TrySendError::Full(_) => "writer is busy",
+            TrySendError::Closed(_) => "writer is stopped",
+        };
+        error!("Dropping point {actual_time}, {reason}");
+        contact_monitoring(state, Check::Influx, MonitorEvent::WriteFailed, Some(format!("Dropped point, {reason}"))).await;
+    }
+}
+
+/// write the points of the poll loop to the sinks, until the poll loop stops
+pub(crate) async fn write_points(state: AppState, mut receiver: Receiver<PendingPoint>) {
+    while let Some(pending) = receiver.recv().await {
+        write_point(&state, pending).await;
+    }
+}
+
+/// write a point to all sinks and contact the monitoring
+async fn write_point(state: &AppState, PendingPoint { point, pings }: PendingPoint) {
+    let config = &state.config;
+    let other_pings = async {
+        for (check, event, body) in pings {
+            contact_monitoring(state, check, event, body).await;
         }
     };
-    // has been checked before
-    #[allow(clippy::unwrap_used)]
-    let body = format!(
-        "{} old={},new={},both={},battery_percentage={},autonomy_percentage={},self_consumption_percentage={},drain_from_battery={},drain_from_grid={},house_consumption={},\
-        wp_charging_values=\"{}\",wp_car_state={},wp_model_status={},wp_wh={},wp_tpcm={},wp_lps={},wp_ets={},wp_power={} \
-        {}",
-        config.influx_measurement.clone().unwrap(),
-        // solar stuff
-        solar.old_inverter_power,
-        solar.new_inverter_power,
-        solar.both_inverter_power,
-        solar.battery_load_percentage,
-        solar.autonomy_percent,
-        solar.self_consumption_percent,
-        solar.drain_from_battery,
-        solar.drain_from_grid,
-        solar.house_consumption,
-        // wp stuff
-        serde_json::to_string(&wp.charging_values).unwrap(),
-        serde_json::to_string(&wp.car_state).unwrap(),
-        serde_json::to_string(&wp.model_status).unwrap(),
-        wp.charged_since_connected,
-        serde_json::to_string(&wp.tpcm).unwrap(),
-        wp.lps,
-        wp.ets,
-        wp.charging_values.pt,
-        // timestamp
-        actual_time.unix_timestamp()
-    );
-    let client = reqwest::Client::new();
-    // unwraps can not panic
-    #[allow(clippy::unwrap_used)]
-    match client
-        .post(format!("{}&precision=s", config.influx_url.clone().unwrap()))
-        .header(AUTHORIZATION, format!("Token {}", config.influx_token.clone().unwrap()))
-        .body(body)
-        .send()
-        .await {
-        Ok(v) => {
-            if v.status().is_success() {
-                contact_monitoring(config, 0, None).await;
-            } else {
-                error!("Influx success Error: {:?}", v);
-                if let Ok(text) = v.text().await {
-                    error!("Influx success Error: {:?}", text);
-                }
-                contact_monitoring(config, 2, Some("Failed to put data into influx".to_owned())).await;
-            }
-        }
-        Err(err) => {
-            error!("Influx response Error: {}", err);
-            contact_monitoring(config, 2, Some("Failed to put data into influx".to_owned())).await;
-        }
+    let writes = join_all(state.sinks.iter().map(|sink| sink.write(&point)));
+    let results = if config.monitoring_concurrent {
+        join!(writes, other_pings).0
+    } else {
+        other_pings.await;
+        writes.await
     };
+    let failed: Vec<String> = state.sinks.iter().zip(results).filter_map(|(sink, result)| {
+        result.err().map(|err| {
+            error!("Failed to put data into {}: {err}", sink.name());
+            format!("Failed to put data into {}: {err}", sink.name())
+        })
+    }).collect();
+    let pending: usize = state.sinks.iter().map(|sink| sink.pending()).sum();
+    if failed.is_empty() {
+        let body = (pending > 0).then(|| format!("{pending} lines buffered"));
+        contact_monitoring(state, Check::Influx, MonitorEvent::Ok, body).await;
+    } else {
+        contact_monitoring(
+            state,
+            Check::Influx,
+            MonitorEvent::WriteFailed,
+            Some(format!("{}\n{pending} lines buffered", failed.join("\n"))),
+        ).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::Result;
+
+    use crate::influx::InfluxSink;
+    use crate::tests::{mock_http, MockResponse, state};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn retried_write_pings_once() -> Result<()> {
+        // the first write fails, the retry succeeds
+        let influx = mock_http(Duration::ZERO, |number, _| {
+            MockResponse::new(if number == 0 { 503 } else { 204 }, "")
+        }).await?;
+        let pings = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&pings);
+        let monitoring = mock_http(Duration::ZERO, move |_, request| {
+            if let Ok(mut requests) = received.lock() {
+                requests.push(request.lines().next().unwrap_or_default().to_owned());
+            }
+            MockResponse::new(200, "")
+        }).await?;
+        let config = Config {
+            influx_url: Some(influx),
+            influx_measurement: Some("solar".to_owned()),
+            influx_retry_delay_ms: 1,
+            healthcheck_url_influx: Some(monitoring.join("influx")?),
+            ..Config::default()
+        };
+        let mut state = state(config);
+        state.sinks = Arc::new(vec![Arc::new(InfluxSink::new(&state.config, &state.client))]);
+        let point = build_point(OffsetDateTime::now_utc(), SolarData::default(), Vec::new());
+        write_point(&state, PendingPoint { point, pings: Vec::new() }).await;
+
+        let sent = pings.lock().map_err(|_| anyhow::anyhow!("Ping lock is poisoned"))?.clone();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].starts_with("POST /influx"));
+        assert_eq!(state.sinks[0].pending(), 0);
+        Ok(())
+    }
 }
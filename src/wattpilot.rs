@@ -1,12 +1,19 @@
 use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+#[cfg(unix)]
+use std::fs::Permissions;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
 use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
 use futures_util::{SinkExt, StreamExt};
 use futures_util::stream::{SplitSink, SplitStream};
+use hmac::{Hmac, Mac};
 use pbkdf2::pbkdf2_hmac_array;
 use poem_openapi::{Enum, Object};
 use rand::Rng;
@@ -16,16 +23,30 @@ use serde_repr::{Deserialize_repr, Serialize_repr};
 use sha2::{Digest, Sha256, Sha512};
 use time::OffsetDateTime;
 use tokio::net::TcpStream;
-use tokio::spawn;
-use tokio::sync::RwLock;
-use tokio::time::sleep;
+use tokio::sync::{Mutex, RwLock};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::select;
+use tokio::task::JoinSet;
+use tokio::time::{interval, sleep, timeout};
+use tokio_util::sync::CancellationToken;
 use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
 use tokio_tungstenite::tungstenite::Message;
 use tracing::{error, info, warn};
-use url::Url;
+use url::{Position, Url};
 
 use crate::config::Config;
 
+/// parse a number which may be sent as integer, float or numeric string, depending on firmware
+fn parse_lenient_f64(value: &Value) -> Option<f64> {
+    #[allow(clippy::wildcard_enum_match_arm)]
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(string) => string.trim().parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 struct HelloMessage {
     serial: String,
@@ -56,25 +77,51 @@ pub(crate) struct WattpilotData {
     /// ?
     pub lps: i64,
     /// ?
-    pub ets: i64
+    pub ets: i64,
+    /// current the car is allowed to draw; data in ampere
+    pub amp: u8,
+    /// how much less current than allowed the car draws on the most loaded phase; data in ampere
+    pub current_delta: f32,
+    /// whether the car draws noticeably less current than allowed while charging,
+    /// usually because its onboard charger limits it
+    pub car_limited: bool,
+    /// whether charging is forced on or off
+    pub force_state: ForceState,
+    /// how many phases are used for charging
+    pub phase_mode: PhaseMode,
+    /// energy charged over the lifetime of the wattpilot; data in Wh
+    pub total_energy_wh: u64,
+    /// maximum current of the connected cable; 0 if there is no cable; data in ampere
+    pub cable_limit_amp: u8,
+    /// raw values of the keys configured in `wattpilot_extra_keys`
+    pub extra: HashMap<String, Value>
 }
 
 impl Default for WattpilotData {
     fn default() -> Self {
         WattpilotData {
             last_updated: OffsetDateTime::UNIX_EPOCH,
-            charging_values: Default::default(),
+            charging_values: ChargingValues::default(),
             car_state: CarState::Unknown,
             model_status: ModelStatus::NotChargingBecauseNoChargeCtrlData,
             charged_since_connected: 0f64,
             tpcm: "[]".to_owned(),
             lps: 0,
-            ets: 0
+            ets: 0,
+            amp: 0,
+            current_delta: 0f32,
+            car_limited: false,
+            force_state: ForceState::Neutral,
+            phase_mode: PhaseMode::Auto,
+            total_energy_wh: 0,
+            cable_limit_amp: 0,
+            extra: HashMap::new()
         }
     }
 }
 
 #[derive(Debug, Clone, Object, Default, Serialize, Deserialize)]
+#[serde(from = "Vec<Option<f32>>")]
 pub(crate) struct ChargingValues {
     // U (L1, L2, L3, N), I (L1, L2, L3),        P (L1, L2, L3, N, Total), pf (L1, L2, L3, N)
     pub(crate) u1: f32,
@@ -95,7 +142,79 @@ pub(crate) struct ChargingValues {
     pub(crate) pfn: f32,
 }
 
-#[derive(Deserialize_repr, Serialize_repr, Clone, Debug, Enum)]
+impl WattpilotData {
+    /// whether the data is recent enough to be used;\
+    /// data is stale after `wattpilot_idle_max_age_secs` without a car, otherwise after 30 seconds
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn is_fresh(&self, config: &Config) -> bool {
+        let age = (OffsetDateTime::now_utc() - self.last_updated).as_seconds_f64();
+        // without a car the charger sends updates less often
+        let max_age = if matches!(self.car_state, CarState::Idle) {
+            config.wattpilot_idle_max_age_secs as f64
+        } else {
+            30f64
+        };
+        age <= max_age
+    }
+}
+
+/// the wattpilot sends `nrg` as flat array in the order of the fields; missing or null values are 0
+impl From<Vec<Option<f32>>> for ChargingValues {
+    fn from(values: Vec<Option<f32>>) -> Self {
+        let value = |index: usize| values.get(index).copied().flatten().unwrap_or_default();
+        ChargingValues {
+            u1: value(0),
+            u2: value(1),
+            u3: value(2),
+            un: value(3),
+            i1: value(4),
+            i2: value(5),
+            i3: value(6),
+            p1: value(7),
+            p2: value(8),
+            p3: value(9),
+            pn: value(10),
+            pt: value(11),
+            pf1: value(12),
+            pf2: value(13),
+            pf3: value(14),
+            pfn: value(15),
+        }
+    }
+}
+
+/// force state of the wattpilot (`frc`)
+#[derive(Deserialize_repr, Serialize_repr, Clone, Copy, Debug, PartialEq, Eq, Enum)]
+#[repr(u8)]
+pub(crate) enum ForceState {
+    Neutral = 0,
+    Off = 1,
+    On = 2,
+}
+
+/// phase switch mode of the wattpilot (`psm`)
+#[derive(Deserialize_repr, Serialize_repr, Clone, Copy, Debug, PartialEq, Eq, Enum)]
+#[repr(u8)]
+pub(crate) enum PhaseMode {
+    Auto = 0,
+    Single = 1,
+    Three = 2,
+}
+
+/// state of the connection to the wattpilot
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enum)]
+pub(crate) enum WattpilotStatus {
+    /// wattpilot is not configured or there is no websocket; data are placeholders
+    Disconnected,
+    /// websocket is open, but the wattpilot is not authenticated yet; data are placeholders
+    Connected,
+    /// wattpilot is authenticated and sends data
+    Authenticated,
+    /// wattpilot is authenticated, but did not send data within the staleness threshold of `WattpilotData::is_fresh`
+    Stale,
+}
+
+#[derive(Deserialize_repr, Serialize_repr, Clone, Copy, Debug, PartialEq, Eq, Enum)]
 #[repr(u16)]
 pub(crate) enum CarState {
     Unknown = 0,
@@ -106,6 +225,19 @@ pub(crate) enum CarState {
     Error = 5,
 }
 
+impl Display for CarState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CarState::Unknown => "Unknown",
+            CarState::Idle => "Idle",
+            CarState::Charging => "Charging",
+            CarState::WaitCar => "WaitCar",
+            CarState::Complete => "Complete",
+            CarState::Error => "Error",
+        })
+    }
+}
+
 #[derive(Serialize_repr, Deserialize_repr, Clone, Debug, Enum)]
 #[repr(u16)]
 pub(crate) enum ModelStatus {
@@ -136,49 +268,306 @@ pub(crate) enum ModelStatus {
 }
 
 
+/// read the cached hash for this serial; `None` if the cache is missing or malformed\
+/// only hashes which were accepted by the wattpilot are cached, a changed password is noticed by its `authError`
+async fn load_cached_hash(path: &Path, serial: &str) -> Option<String> {
+    let content = fs::read_to_string(path).await.ok()?;
+    let mut cache: HashMap<String, String> = match serde_json::from_str(&content) {
+        Ok(v) => v,
+        Err(err) => {
+            warn!("Ignoring malformed wattpilot hash cache {}: {err}", path.display());
+            return None;
+        }
+    };
+    cache.remove(serial)
+}
+
+/// serializes the updates of the hash cache, several wattpilots may store their hash at the same time
+static HASH_CACHE_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// set the hash for this serial in the cache, or remove it without a hash; readable by the owner only;\
+/// the cache is written to a temporary file first and then renamed, so readers never see a partial file
+async fn store_cached_hash(path: &Path, serial: &str, hash: Option<&str>) -> Result<()> {
+    let _guard = HASH_CACHE_LOCK.lock().await;
+    let mut cache: HashMap<String, String> = fs::read_to_string(path).await.ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+    match hash {
+        Some(hash) => cache.insert(serial.to_owned(), hash.to_owned()),
+        None => cache.remove(serial),
+    };
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    let mut file = options.open(&temporary).await?;
+    // an existing file keeps its permissions otherwise
+    #[cfg(unix)]
+    fs::set_permissions(&temporary, Permissions::from_mode(0o600)).await?;
+    file.write_all(serde_json::to_string(&cache)?.as_bytes()).await?;
+    file.sync_all().await?;
+    drop(file);
+    fs::rename(&temporary, path).await?;
+    Ok(())
+}
+
+/// hash the password with the serial of the wattpilot, like the app does; slow on purpose
+fn hash_password(password: &str, serial: &str) -> String {
+    let array = pbkdf2_hmac_array::<Sha512, 32>(password.as_bytes(), serial.as_bytes(), 100_000);
+    BASE64_STANDARD.encode(array)[..32].to_owned()
+}
+
+/// hmac of a secured message, keyed with the hashed password; lowercase hex
+fn sign(hashed_pw: &str, payload: &str) -> Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hashed_pw.as_bytes())?;
+    mac.update(payload.as_bytes());
+    Ok(format!("{:x}", mac.finalize().into_bytes()))
+}
+
+/// difference between allowed and drawn current from which on the car counts as limited; data in ampere
+const CAR_LIMITED_THRESHOLD: f32 = 1.0;
+
+/// delay before the first reconnect to the wattpilot
+const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(3);
+/// maximum delay between reconnects to the wattpilot
+const RECONNECT_MAX_DELAY: Duration = Duration::from_mins(1);
+/// maximum random delay added to every reconnect; data in milliseconds
+const RECONNECT_MAX_JITTER_MS: u64 = 1000;
+/// how long a connection has to stay up to reset the reconnect delay
+const RECONNECT_RESET_AFTER: Duration = Duration::from_mins(1);
+
+type WebsocketWrite = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
+
+/// what the authentication needs, copied out of the wattpilot so its lock is not held while waiting for the network
+struct Handshake {
+    password: String,
+    /// serial of the wattpilot and the password hashed with it, from the last authentication
+    hashed_pw: Option<(String, String)>,
+    /// how long to wait for the connection and for each message of the authentication
+    timeout: Duration,
+    /// file the hashed passwords are cached in
+    hash_cache: Option<PathBuf>,
+}
+
+impl Handshake {
+    /// authenticate on a new connection; returns whether the wattpilot wants secured messages
+    async fn authenticate(
+        &mut self,
+        write: &mut WebsocketWrite,
+        read: &mut SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
+    ) -> Result<bool> {
+        let Some(hello) = timeout(self.timeout, read.next()).await
+            .map_err(|_| anyhow!("Timeout waiting for 'hello' message"))? else {
+            return Err(anyhow!("No data for 'hello' message"));
+        };
+        let hello_message: HelloMessage = serde_json::from_str(hello?.to_text()?)?;
+        let Some(auth) = timeout(self.timeout, read.next()).await
+            .map_err(|_| anyhow!("Timeout waiting for 'auth' message"))? else {
+            return Err(anyhow!("No data for 'auth' message"));
+        };
+        let auth_message: AuthRequiredMessage = serde_json::from_str(auth?.to_text()?)?;
+
+        // the hash depends on the serial, so it has to be computed again if the device changes
+        let serial = hello_message.serial.clone();
+        let mut computed = false;
+        let hashed_pw = match &self.hashed_pw {
+            Some((known, hashed_pw)) if *known == serial => hashed_pw.clone(),
+            Some(_) | None => {
+                let cached = match &self.hash_cache {
+                    None => None,
+                    Some(path) => load_cached_hash(path, &serial).await,
+                };
+                let hashed_pw = cached.unwrap_or_else(|| {
+                    computed = true;
+                    hash_password(&self.password, &serial)
+                });
+                self.hashed_pw = Some((serial.clone(), hashed_pw.clone()));
+                hashed_pw
+            }
+        };
+
+        let mut hasher1 = Sha256::new();
+        hasher1.update(auth_message.token1 + &hashed_pw);
+
+        let token3 = &format!("{:#032x}", rand::thread_rng().gen_range(u128::MAX / 2..u128::MAX))[2..34].to_owned();
+        let mut hasher2 = Sha256::new();
+        hasher2.update(token3.to_owned() + &auth_message.token2 + &format!("{:x}", hasher1.finalize()));
+
+        write.send(Message::from(
+            json!({"type": "auth", "token3": token3, "hash": &format!("{:x}", hasher2.finalize())}).to_string()
+        )).await?;
+
+        let Some(response) = timeout(self.timeout, read.next()).await
+            .map_err(|_| anyhow!("Timeout waiting for 'auth' response"))? else {
+            return Err(anyhow!("No data for 'auth' response"));
+        };
+        let v: Value = serde_json::from_str(response?.to_text()?)?;
+        if v["type"] == "authError" {
+            error!("Authentication failed! {}", v["message"]);
+            // the password may have changed, the hash is computed again with the next try
+            self.hashed_pw = None;
+            self.update_hash_cache(&serial, None).await;
+            return Err(anyhow!("Wattpilot rejected authentication: {}", v["message"]));
+        }
+        if v["type"] != "authSuccess" {
+            error!("Unexpected response to authentication: {}", v);
+            return Err(anyhow!("Unexpected response to authentication: {}", v["type"]));
+        }
+        info!("Authentication succeeded!");
+        if computed {
+            self.update_hash_cache(&serial, Some(&hashed_pw)).await;
+        }
+        Ok(hello_message.secured)
+    }
+
+    /// store or remove the hash of this serial in the cache, if there is one
+    async fn update_hash_cache(&self, serial: &str, hash: Option<&str>) {
+        if let Some(path) = &self.hash_cache {
+            if let Err(err) = store_cached_hash(path, serial, hash).await {
+                warn!("Could not write wattpilot hash cache {}: {err}", path.display());
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct Wattpilot {
+    /// id of the wattpilot, host and port of its url
+    pub(crate) id: String,
     secured: bool,
-    hashed_pw: String,
+    password: String,
+    /// serial of the wattpilot and the password hashed with it
+    hashed_pw: Option<(String, String)>,
     url: Url,
     pub(crate) data: Arc<RwLock<WattpilotData>>,
-    write: Arc<RwLock<Option<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>>>>,
-    pub(crate) authenticated: bool
+    write: Arc<RwLock<Option<WebsocketWrite>>>,
+    /// whether the websocket is open, authenticated or not
+    connected: bool,
+    pub(crate) authenticated: bool,
+    /// increased for every started handler, only the handler of the current generation keeps its connection
+    generation: u64,
+    /// id of the last message sent
+    message_id: u64,
+    /// interval for websocket pings; zero = no pings
+    keepalive: Duration,
+    /// cancelled when the application shuts down
+    shutdown: CancellationToken,
+    /// how often the websocket was reconnected
+    pub(crate) reconnect_count: u64,
+    /// last error of the connection
+    pub(crate) last_error: Option<String>,
+    /// how long to wait for the connection and for each message of the authentication
+    handshake_timeout: Duration,
+    /// additional status keys whose raw values are kept
+    extra_keys: Vec<String>,
+    /// file the hashed passwords are cached in
+    hash_cache: Option<PathBuf>
 }
 
 impl Wattpilot {
-    pub(crate) fn new(config: &Config) -> Option<Arc<RwLock<Wattpilot>>> {
-        if config.wattpilot_url.is_none() || config.wattpilot_password.is_none() {
-            info!("Wattpilot url or wattpilot password is not set, wattpilot feature deactivated!");
-            None
+    /// urls and passwords of the configured wattpilots
+    pub(crate) fn devices(config: &Config) -> Result<Vec<(Url, String)>> {
+        let urls = config.wattpilot_url.split(',').map(str::trim).filter(|s| !s.is_empty())
+            .map(Url::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        let passwords = if config.wattpilot_passwords.is_empty() {
+            let Some(password) = &config.wattpilot_password else {
+                return Ok(Vec::new());
+            };
+            vec![password.clone(); urls.len()]
         } else {
-            #[allow(clippy::unwrap_used)]
-                let Ok(url) = config.wattpilot_url.clone().unwrap().join("ws") else {
+            config.wattpilot_passwords.clone()
+        };
+        if passwords.len() != urls.len() {
+            return Err(anyhow!("Got {} wattpilot urls, but {} passwords", urls.len(), passwords.len()));
+        }
+        Ok(urls.into_iter().zip(passwords).collect())
+    }
+
+    /// create and connect all configured wattpilots; their handlers are spawned on `handlers`
+    /// and close their connection when `shutdown` is cancelled
+    pub(crate) fn new(config: &Config, handlers: &mut JoinSet<()>, shutdown: &CancellationToken) -> Vec<Arc<RwLock<Wattpilot>>> {
+        let devices = match Wattpilot::devices(config) {
+            Ok(devices) => devices,
+            Err(err) => {
+                error!("Invalid wattpilot config: {err}");
+                return Vec::new();
+            }
+        };
+        if devices.is_empty() {
+            info!("Wattpilot url or wattpilot password is not set, wattpilot feature deactivated!");
+        }
+        devices.into_iter().filter_map(|(url, password)| {
+            let id = url[Position::BeforeHost..Position::AfterPort].to_owned();
+            let Ok(url) = url.join("ws") else {
                 error!("Failed to concat url in wattpilot main loop");
                 return None;
             };
-            // can not panic, because we tested above, that both values exist!
             let wp = Arc::new(RwLock::new(Wattpilot {
+                id,
                 secured: false,
-                hashed_pw: String::new(),
+                password,
+                hashed_pw: None,
                 url,
+                connected: false,
                 authenticated: false,
                 data: Arc::default(),
-                write: Arc::default()
+                write: Arc::default(),
+                generation: 0,
+                message_id: 0,
+                keepalive: Duration::from_secs(config.wattpilot_keepalive_secs),
+                shutdown: shutdown.child_token(),
+                reconnect_count: 0,
+                last_error: None,
+                handshake_timeout: Duration::from_secs(config.wattpilot_handshake_timeout_secs),
+                extra_keys: config.wattpilot_extra_keys.split(',').map(str::trim)
+                    .filter(|s| !s.is_empty()).map(str::to_owned).collect(),
+                hash_cache: config.wattpilot_hash_cache.clone().map(PathBuf::from)
             }));
-            let config_clone = config.clone();
             let wp_clone = Arc::clone(&wp);
-            spawn(async {
-                Wattpilot::main_handler(wp_clone, config_clone).await;
+            handlers.spawn(async {
+                Wattpilot::start_handler(wp_clone).await;
             });
             Some(wp)
+        }).collect()
+    }
+
+    /// find the wattpilot with the given id; the first one, if no id is given
+    pub(crate) async fn find(wps: &[Arc<RwLock<Wattpilot>>], id: Option<&str>) -> Option<Arc<RwLock<Wattpilot>>> {
+        let Some(id) = id else {
+            return wps.first().cloned();
+        };
+        for wp in wps {
+            if wp.read().await.id == id {
+                return Some(Arc::clone(wp));
+            }
+        }
+        None
+    }
+
+    /// total charging power of all wattpilots; wattpilots with too old data count as not charging; data in watts
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub(crate) async fn charging_power(wps: &[Arc<RwLock<Wattpilot>>], config: &Config) -> u32 {
+        let mut power = 0f32;
+        for wp in wps {
+            let read = wp.read().await;
+            let data = read.data.read().await;
+            if read.authenticated && data.is_fresh(config) {
+                power += data.charging_values.pt;
+            }
         }
+        power.max(0.0).round() as u32
     }
 
     pub async fn send(&self, secure: bool, payload: String, message_id: &str) -> Result<()> {
         let message = if secure {
-            let hmac = "";
-            //h = hmac.new(bytearray(self._hashedpassword), bytearray(payload.encode()), hashlib.sha256)
+            let Some((_, hashed_pw)) = &self.hashed_pw else {
+                return Err(anyhow!("Could not sign message because not authenticated: {}", payload));
+            };
+            let hmac = sign(hashed_pw, &payload)?;
             json!({
             "type": "securedMsg", "data": payload, "requestId": message_id.to_owned() + "sm", "hmac": hmac
         }).to_string()
@@ -190,104 +579,239 @@ impl Wattpilot {
         };
         if let Err(err) = write.send(Message::from(message)).await {
             return Err(anyhow!(err));
-        };
+        }
         Ok(())
     }
 
-    async fn authenticate(
-        &mut self,
-        password: String,
-        read: &mut SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
-    ) -> Result<()> {
-        let Some(x) = read.next().await else {
-            return Err(anyhow!("No data for 'hello' message"));
+    /// state of the connection to the wattpilot, if it is configured;\
+    /// derived from the websocket, the authentication and the age of the data
+    pub(crate) async fn status(wp: Option<&Arc<RwLock<Wattpilot>>>, config: &Config) -> WattpilotStatus {
+        let Some(wp) = wp else {
+            return WattpilotStatus::Disconnected;
         };
-        let hello_message: HelloMessage = serde_json::from_str(x?.to_text()?)?;
-        let Some(x) = read.next().await else {
-            return Err(anyhow!("No data for 'auth' message"));
-        };
-        let auth_message: AuthRequiredMessage = serde_json::from_str(x?.to_text()?)?;
-
-        if self.hashed_pw.is_empty() {
-            let array = pbkdf2_hmac_array::<Sha512, 32>(password.as_ref(), hello_message.serial.as_ref(), 100_000);
-            self.hashed_pw = BASE64_STANDARD.encode(array)[..32].to_owned();
+        let read = wp.read().await;
+        if !read.connected {
+            return WattpilotStatus::Disconnected;
         }
+        if !read.authenticated {
+            return WattpilotStatus::Connected;
+        }
+        if read.data.read().await.is_fresh(config) {
+            WattpilotStatus::Authenticated
+        } else {
+            WattpilotStatus::Stale
+        }
+    }
 
-        let mut hasher1 = Sha256::new();
-        hasher1.update(auth_message.token1 + &self.hashed_pw);
-
-        let token3 = &format!("{:#032x}", rand::thread_rng().gen_range(u128::MAX / 2..u128::MAX))[2..34].to_owned();
-        let mut hasher2 = Sha256::new();
-        hasher2.update(token3.to_owned() + &auth_message.token2 + &format!("{:x}", hasher1.finalize()));
-
+    /// set a value of the wattpilot, e.g. `amp`
+    pub(crate) async fn set_value(&mut self, key: &str, value: Value) -> Result<()> {
+        self.message_id += 1;
+        let message_id = self.message_id.to_string();
         self.send(
-            false,
-            json!({"type": "auth", "token3": token3, "hash": &format!("{:x}", hasher2.finalize())}).to_string(),
-            "",
-        ).await?;
+            self.secured,
+            json!({"type": "setValue", "requestId": message_id, "key": key, "value": value}).to_string(),
+            &message_id,
+        ).await
+    }
 
-        let Some(x) = read.next().await else {
-            return Err(anyhow!("No data for 'auth' response"));
+    /// start a new connection handler; older handlers stop before their next connection attempt or message
+    async fn start_handler(wp: Arc<RwLock<Wattpilot>>) {
+        let generation = {
+            let mut lock = wp.write().await;
+            lock.generation += 1;
+            lock.generation
         };
-        let v: Value = serde_json::from_str(x?.to_text()?)?;
-        if v["type"] == "authError" {
-            error!("Authentication failed! {}", v["message"]);
-            self.authenticated = false;
+        Wattpilot::main_handler(wp, generation).await;
+    }
+
+    /// send a close frame through the websocket
+    async fn close(socket: &RwLock<Option<WebsocketWrite>>) {
+        if let Some(sink) = socket.write().await.as_mut() {
+            if let Err(err) = sink.send(Message::Close(None)).await {
+                error!("Could not close wattpilot websocket: {err}");
+            }
         }
-        if v["type"] == "authSuccess" {
-            info!("Authentication succeeded!");
-            self.authenticated = true;
+    }
+
+    /// sleep for the given delay plus some jitter and double the delay for the next try;
+    /// returns false if the application shuts down meanwhile
+    async fn backoff(delay: &mut Duration, shutdown: &CancellationToken) -> bool {
+        let jitter = Duration::from_millis(rand::thread_rng().gen_range(0..=RECONNECT_MAX_JITTER_MS));
+        let wait = *delay + jitter;
+        *delay = (*delay * 2).min(RECONNECT_MAX_DELAY);
+        select! {
+            () = sleep(wait) => true,
+            () = shutdown.cancelled() => false,
         }
-        Ok(())
     }
 
-    pub(crate) async fn main_handler(wp: Arc<RwLock<Wattpilot>>, config: Config) {
+    #[allow(clippy::too_many_lines)]
+    async fn main_handler(wp: Arc<RwLock<Wattpilot>>, generation: u64) {
+        let mut delay = RECONNECT_BASE_DELAY;
+        let shutdown = wp.read().await.shutdown.clone();
+        let mut reconnect = false;
         loop {
+            if shutdown.is_cancelled() {
+                return;
+            }
             info!("Trying to connect to wattpilot ...");
-            let mut wp_write = wp.write().await;
-            wp_write.authenticated = false;
-            let (stream, _) = match connect_async(wp_write.url.clone()).await {
-                Ok(x) => { x }
-                Err(err) => {
-                    error!("Error while connecting to wattpilot: {:#?}", err);
-                    sleep(Duration::from_secs(3)).await;
+            // the lock is only held to read the settings and to store the result, not while waiting for the network
+            let (url, mut handshake) = {
+                let mut lock = wp.write().await;
+                if lock.generation != generation {
+                    info!("Newer wattpilot connection active, stopping this one");
+                    return;
+                }
+                lock.connected = false;
+                lock.authenticated = false;
+                if reconnect {
+                    lock.reconnect_count += 1;
+                }
+                (lock.url.clone(), Handshake {
+                    password: lock.password.clone(),
+                    hashed_pw: lock.hashed_pw.clone(),
+                    timeout: lock.handshake_timeout,
+                    hash_cache: lock.hash_cache.clone(),
+                })
+            };
+            reconnect = true;
+            let stream = match timeout(handshake.timeout, connect_async(url)).await {
+                Ok(Ok((stream, _))) => stream,
+                result => {
+                    let err = match result {
+                        Ok(Err(err)) => err.to_string(),
+                        Ok(Ok(_)) | Err(_) => format!("Timeout after {}s", handshake.timeout.as_secs()),
+                    };
+                    wp.write().await.last_error = Some(format!("Connection failed: {err}"));
+                    error!("Error while connecting to wattpilot, retrying in {}s: {err}", delay.as_secs());
+                    if !Wattpilot::backoff(&mut delay, &shutdown).await {
+                        return;
+                    }
                     continue;
                 }
             };
             info!("Wattpilot Websocket connected.");
-            let (write, mut read) = stream.split();
-            wp_write.write = Arc::new(RwLock::new(Some(write)));
-            // can not be none, we tested before
-            #[allow(clippy::unwrap_used)]
-            if let Err(err) = wp_write.authenticate(config.wattpilot_password.clone().unwrap(), &mut read).await {
-                error!("Websocket authentication failed!");
-                error!("{:#?}", err);
-                wp.write().await.authenticated = false;
-                sleep(Duration::from_secs(3)).await;
-                info!("Trying to connect to wattpilot again...");
-                continue;
+            wp.write().await.connected = true;
+            let (mut write, mut read) = stream.split();
+            let result = handshake.authenticate(&mut write, &mut read).await;
+            let mut wp_write = wp.write().await;
+            // a computed hash is kept, even if the wattpilot rejected the authentication
+            wp_write.hashed_pw = handshake.hashed_pw;
+            if wp_write.generation != generation {
+                drop(wp_write);
+                info!("Newer wattpilot connection active, closing this one");
+                if let Err(err) = write.send(Message::Close(None)).await {
+                    error!("Could not close wattpilot websocket: {err}");
+                }
+                return;
+            }
+            match result {
+                Ok(secured) => {
+                    wp_write.secured = secured;
+                    wp_write.write = Arc::new(RwLock::new(Some(write)));
+                    wp_write.authenticated = true;
+                }
+                Err(err) => {
+                    wp_write.connected = false;
+                    wp_write.last_error = Some(format!("Authentication failed: {err}"));
+                    drop(wp_write);
+                    error!("Websocket authentication failed!");
+                    error!("{:#?}", err);
+                    info!("Trying to connect to wattpilot again in {}s...", delay.as_secs());
+                    if !Wattpilot::backoff(&mut delay, &shutdown).await {
+                        return;
+                    }
+                    continue;
+                }
             }
             let data = Arc::clone(&wp_write.data);
+            let socket = Arc::clone(&wp_write.write);
+            let keepalive = wp_write.keepalive;
+            let extra_keys = wp_write.extra_keys.clone();
             drop(wp_write);
-            while let Some(message) = read.next().await {
-                if let Ok(msg) = message {
-                    if let Ok(text) = msg.to_text() {
-                        Wattpilot::read_message(&data, text).await;
+            let connected = Instant::now();
+            let mut last_message = Instant::now();
+            // interval panics with a zero period, the branch is disabled in this case anyway
+            let mut ping = interval(keepalive.max(Duration::from_secs(1)));
+            ping.tick().await;
+            let mut closed_because = "Connection closed by wattpilot".to_owned();
+            loop {
+                let message = select! {
+                    message = read.next() => message,
+                    () = shutdown.cancelled() => {
+                        info!("Shutting down, closing wattpilot websocket");
+                        Wattpilot::close(&socket).await;
+                        let mut lock = wp.write().await;
+                        lock.connected = false;
+                        lock.authenticated = false;
+                        return;
+                    }
+                    _ = ping.tick(), if !keepalive.is_zero() => {
+                        if last_message.elapsed() > keepalive * 2 {
+                            closed_because = format!("No message from wattpilot for {}s", last_message.elapsed().as_secs());
+                            error!("{closed_because}, restarting websocket");
+                            break;
+                        }
+                        let sent = match socket.write().await.as_mut() {
+                            None => Err(anyhow!("No websocket to send ping")),
+                            Some(sink) => sink.send(Message::Ping(Vec::new())).await.map_err(|err| anyhow!(err)),
+                        };
+                        if let Err(err) = sent {
+                            closed_because = format!("Could not send ping: {err}");
+                            error!("Could not send ping to wattpilot, restarting websocket: {err}");
+                            break;
+                        }
+                        continue;
                     }
-                } else {
-                    error!("Error receiving message, restarting websocket");
-                    wp.write().await.authenticated = false;
+                };
+                let Some(message) = message else {
+                    break;
+                };
+                last_message = Instant::now();
+                if wp.read().await.generation != generation {
+                    info!("Newer wattpilot connection active, closing this one");
+                    Wattpilot::close(&socket).await;
+                    drop(read);
+                    return;
                 }
+                match message {
+                    Ok(msg) => {
+                        if let Ok(text) = msg.to_text() {
+                            Wattpilot::read_message(&data, text, &extra_keys).await;
+                        }
+                    }
+                    Err(err) => {
+                        error!("Error receiving message, restarting websocket");
+                        closed_because = format!("Error receiving message: {err}");
+                        wp.write().await.authenticated = false;
+                    }
+                }
+            }
+            {
+                let mut lock = wp.write().await;
+                lock.connected = false;
+                lock.authenticated = false;
+                lock.last_error = Some(closed_because);
+            }
+            if connected.elapsed() >= RECONNECT_RESET_AFTER {
+                delay = RECONNECT_BASE_DELAY;
+            }
+            info!("Wattpilot websocket closed, reconnecting in {}s", delay.as_secs());
+            if !Wattpilot::backoff(&mut delay, &shutdown).await {
+                return;
             }
-            sleep(Duration::from_secs(3)).await;
         }
     }
 
-    #[allow(clippy::shadow_unrelated)]
-    async fn read_message(data: &Arc<RwLock<WattpilotData>>, message: &str) {
+    #[allow(clippy::shadow_unrelated, clippy::too_many_lines)]
+    async fn read_message(data: &Arc<RwLock<WattpilotData>>, message: &str, extra_keys: &[String]) {
         let Ok(v) = serde_json::from_str::<Value>(message) else {
             return;
         };
+        // `fullStatus` carries all keys, `deltaStatus` only the changed ones; both are merged into the existing data
+        if !["fullStatus", "deltaStatus"].contains(&v["type"].as_str().unwrap_or_default()) {
+            return;
+        }
         let Some(status) = v.get("status") else {
             return;
         };
@@ -295,7 +819,8 @@ impl Wattpilot {
             return;
         };
         let reduced: HashMap<&str, &Value> = obj.iter().filter_map(|(key, value)| {
-            if ["nrg", "car", "modelStatus", "wh", "tpcm", "lps", "ets"].contains(&&**key) {
+            if ["nrg", "car", "modelStatus", "wh", "tpcm", "lps", "ets", "amp", "frc", "psm", "eto", "cbl"].contains(&&**key)
+                || extra_keys.contains(key) {
                 return Some((key.as_str(), value));
             }
             None
@@ -314,6 +839,14 @@ impl Wattpilot {
         }
         if let Some(data) = reduced.get("car") {
             if let Ok(parsed_value) = serde_json::from_value::<CarState>((*data).clone()) {
+                if lock.car_state != parsed_value {
+                    // the first state after start is no transition
+                    if lock.car_state == CarState::Unknown {
+                        info!("Car state: {parsed_value}");
+                    } else {
+                        info!("Car state: {} -> {parsed_value}", lock.car_state);
+                    }
+                }
                 lock.car_state = parsed_value;
             } else {
                 warn!("Could not parse as car: {}", data);
@@ -327,7 +860,7 @@ impl Wattpilot {
             }
         }
         if let Some(data) = reduced.get("wh") {
-            if let Ok(parsed_value) = serde_json::from_value::<f64>((*data).clone()) {
+            if let Some(parsed_value) = parse_lenient_f64(data) {
                 lock.charged_since_connected = parsed_value;
             } else {
                 warn!("Could not parse as wh: {}", data);
@@ -363,6 +896,463 @@ impl Wattpilot {
                 warn!("Could not parse as ets: {}", data);
             }
         }
+        if let Some(data) = reduced.get("amp") {
+            if let Ok(parsed_value) = serde_json::from_value::<u8>((*data).clone()) {
+                lock.amp = parsed_value;
+            } else {
+                warn!("Could not parse as amp: {}", data);
+            }
+        }
+        if let Some(data) = reduced.get("frc") {
+            if let Ok(parsed_value) = serde_json::from_value::<ForceState>((*data).clone()) {
+                lock.force_state = parsed_value;
+            } else {
+                warn!("Could not parse as frc: {}", data);
+            }
+        }
+        if let Some(data) = reduced.get("psm") {
+            if let Ok(parsed_value) = serde_json::from_value::<PhaseMode>((*data).clone()) {
+                lock.phase_mode = parsed_value;
+            } else {
+                warn!("Could not parse as psm: {}", data);
+            }
+        }
+        if let Some(data) = reduced.get("eto") {
+            if let Ok(parsed_value) = serde_json::from_value::<u64>((*data).clone()) {
+                lock.total_energy_wh = parsed_value;
+            } else {
+                warn!("Could not parse as eto: {}", data);
+            }
+        }
+        if let Some(data) = reduced.get("cbl") {
+            // null without a cable
+            if let Ok(parsed_value) = serde_json::from_value::<Option<u8>>((*data).clone()) {
+                lock.cable_limit_amp = parsed_value.unwrap_or_default();
+            } else {
+                warn!("Could not parse as cbl: {}", data);
+            }
+        }
+        for key in extra_keys {
+            if let Some(data) = reduced.get(key.as_str()) {
+                lock.extra.insert(key.clone(), (*data).clone());
+            }
+        }
+        let drawn = lock.charging_values.i1.max(lock.charging_values.i2).max(lock.charging_values.i3);
+        lock.current_delta = f32::from(lock.amp) - drawn;
+        lock.car_limited = matches!(lock.car_state, CarState::Charging) && lock.current_delta > CAR_LIMITED_THRESHOLD;
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio_tungstenite::accept_async;
+
+    use super::*;
+
+    /// serial the mock wattpilot reports
+    const SERIAL: &str = "91234567";
+
+    /// connection of the mock wattpilot to the tested client
+    type MockSocket = WebSocketStream<TcpStream>;
+
+    /// wattpilot connecting to the given url; the hash for `SERIAL` is known already, to skip the slow hashing
+    fn wattpilot(url: Url) -> Wattpilot {
+        Wattpilot {
+            id: "mock".to_owned(),
+            secured: false,
+            password: "password".to_owned(),
+            hashed_pw: Some((SERIAL.to_owned(), "h".repeat(32))),
+            url,
+            data: Arc::default(),
+            write: Arc::default(),
+            connected: false,
+            authenticated: false,
+            generation: 0,
+            message_id: 0,
+            keepalive: Duration::ZERO,
+            shutdown: CancellationToken::new(),
+            reconnect_count: 0,
+            last_error: None,
+            handshake_timeout: Duration::from_secs(5),
+            extra_keys: Vec::new(),
+            hash_cache: None,
+        }
+    }
+
+    /// accept websocket connections and run `script` for each of them, together with the number of the connection;
+    /// returns the url of the mock wattpilot
+    async fn mock_wattpilot<F, Fut>(script: F) -> Result<Url>
+    where
+        F: Fn(MockSocket, usize) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = Url::parse(&format!("ws://{}/ws", listener.local_addr()?))?;
+        tokio::spawn(async move {
+            let mut connection = 0;
+            while let Ok((stream, _)) = listener.accept().await {
+                if let Ok(socket) = accept_async(stream).await {
+                    tokio::spawn(script(socket, connection));
+                    connection += 1;
+                }
+            }
+        });
+        Ok(url)
+    }
+
+    /// send `hello` and `authRequired`, wait for the `auth` message and answer it with `response`
+    async fn handshake(socket: &mut MockSocket, serial: &str, response: Value) -> Result<()> {
+        socket.send(Message::from(json!({"type": "hello", "serial": serial, "secured": true}).to_string())).await?;
+        socket.send(Message::from(json!({"type": "authRequired", "token1": "abc", "token2": "def"}).to_string())).await?;
+        let auth = socket.next().await.ok_or_else(|| anyhow!("No auth message"))??;
+        let auth: Value = serde_json::from_str(auth.to_text()?)?;
+        if auth["type"] != "auth" {
+            return Err(anyhow!("Expected auth message, got {auth}"));
+        }
+        socket.send(Message::from(response.to_string())).await?;
+        Ok(())
+    }
+
+    /// settings for authenticating at the mock wattpilot
+    fn handshake_settings(hashed_pw: Option<(String, String)>, wait: Duration) -> Handshake {
+        Handshake { password: "password".to_owned(), hashed_pw, timeout: wait, hash_cache: None }
+    }
+
+    /// authenticate at the mock wattpilot with the given settings
+    async fn authenticate(url: Url, handshake: &mut Handshake) -> Result<bool> {
+        let (stream, _) = connect_async(url).await?;
+        let (mut write, mut read) = stream.split();
+        handshake.authenticate(&mut write, &mut read).await
+    }
+
+    #[tokio::test]
+    async fn concurrent_hash_cache_writes_keep_every_entry() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("wattpilot-hash-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).await?;
+        let path = dir.join("hashes.json");
+        let mut writes = JoinSet::new();
+        for index in 0..20 {
+            let path = path.clone();
+            writes.spawn(async move { store_cached_hash(&path, &format!("serial{index}"), Some("hash")).await });
+        }
+        while let Some(result) = writes.join_next().await {
+            result??;
+        }
+        for index in 0..20 {
+            assert_eq!(load_cached_hash(&path, &format!("serial{index}")).await.as_deref(), Some("hash"));
+        }
+        // only hashes are stored, nothing which could be checked against a guessed password quickly
+        let content = fs::read_to_string(&path).await?;
+        assert_eq!(serde_json::from_str::<HashMap<String, String>>(&content)?.len(), 20);
+        store_cached_hash(&path, "serial0", None).await?;
+        assert_eq!(load_cached_hash(&path, "serial0").await, None);
+        fs::remove_dir_all(&dir).await?;
+        Ok(())
+    }
+
+    #[test]
+    fn hash_password_like_the_app() {
+        assert_eq!(hash_password("wattpilot1234", "91234567"), "4VHVWdpAweYx83PKVDSwGs7XaKxEK9ET");
+    }
+
+    #[test]
+    fn nrg_array_fills_the_fields_in_order() -> Result<()> {
+        // sample shaped like the `nrg` of a wattpilot charging on three phases; every value is distinct
+        let nrg: ChargingValues = serde_json::from_value(json!(
+            [231, 232, 233, 2, 10.1, 10.2, 10.3, 2300, 2310, 2320, 5, 6930, 99, 98, 97, 1]
+        ))?;
+        let fields = [
+            nrg.u1, nrg.u2, nrg.u3, nrg.un, nrg.i1, nrg.i2, nrg.i3, nrg.p1,
+            nrg.p2, nrg.p3, nrg.pn, nrg.pt, nrg.pf1, nrg.pf2, nrg.pf3, nrg.pfn,
+        ];
+        let expected = [
+            231f32, 232f32, 233f32, 2f32, 10.1, 10.2, 10.3, 2300f32,
+            2310f32, 2320f32, 5f32, 6930f32, 99f32, 98f32, 97f32, 1f32,
+        ];
+        for (index, (field, value)) in fields.iter().zip(expected).enumerate() {
+            assert!((field - value).abs() < f32::EPSILON, "index {index}: {field} != {value}");
+        }
+
+        // older firmware sends fewer values and null for missing phases
+        let short: ChargingValues = serde_json::from_value(json!([230, null, null, 0, 6.1]))?;
+        assert!((short.u1 - 230f32).abs() < f32::EPSILON);
+        assert!((short.i1 - 6.1).abs() < f32::EPSILON);
+        assert!(short.u2.abs() < f32::EPSILON && short.i2.abs() < f32::EPSILON && short.pt.abs() < f32::EPSILON);
+        Ok(())
+    }
+
+    #[test]
+    fn devices_match_passwords_to_urls() -> Result<()> {
+        let config = Config {
+            wattpilot_url: "http://wp1, http://wp2".to_owned(),
+            wattpilot_password: Some("pass,word".to_owned()),
+            ..Config::default()
+        };
+        // a shared password is never split
+        let passwords: Vec<String> = Wattpilot::devices(&config)?.into_iter().map(|(_, password)| password).collect();
+        assert_eq!(passwords, ["pass,word", "pass,word"]);
+
+        let separate = Config { wattpilot_passwords: vec!["first".to_owned(), "sec,ond".to_owned()], ..config.clone() };
+        let devices = Wattpilot::devices(&separate)?;
+        assert_eq!(devices[1].0.as_str(), "http://wp2/");
+        assert_eq!(devices[1].1, "sec,ond");
+
+        let missing = Config { wattpilot_passwords: vec!["first".to_owned()], ..config };
+        assert!(Wattpilot::devices(&missing).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_message_merges_full_and_delta_status() {
+        let data = Arc::new(RwLock::new(WattpilotData::default()));
+        let full = json!({"type": "fullStatus", "partial": false, "status": {
+            "car": 1, "amp": 16, "frc": 0, "psm": 2, "eto": 123_456, "cbl": 32, "wh": "0.0",
+            "nrg": [230, 231, 229, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], "fwv": "40.7",
+        }});
+        Wattpilot::read_message(&data, &full.to_string(), &[]).await;
+        {
+            let read = data.read().await;
+            assert_eq!(read.car_state, CarState::Idle);
+            assert_eq!((read.amp, read.total_energy_wh, read.cable_limit_amp), (16, 123_456, 32));
+            assert_eq!(read.phase_mode, PhaseMode::Three);
+            assert!((read.charging_values.u2 - 231f32).abs() < f32::EPSILON);
+        }
+
+        let delta = json!({"type": "deltaStatus", "partial": true, "status": {"car": 2}});
+        Wattpilot::read_message(&data, &delta.to_string(), &[]).await;
+        let read = data.read().await;
+        assert_eq!(read.car_state, CarState::Charging);
+        // everything else keeps the values of the full status
+        assert_eq!((read.amp, read.total_energy_wh, read.cable_limit_amp), (16, 123_456, 32));
+        assert_eq!(read.phase_mode, PhaseMode::Three);
+        assert!((read.charging_values.u2 - 231f32).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn sign_secured_message() -> Result<()> {
+        let payload = r#"{"type":"setValue","requestId":"1","key":"amp","value":16}"#;
+        assert_eq!(
+            sign("4VHVWdpAweYx83PKVDSwGs7XaKxEK9ET", payload)?,
+            "c0afc34a2b793ffcea882fb1850ab471b7c11544c65c5acb5db01413b69c951e",
+        );
+        Ok(())
+    }
+
+    /// wait until the wattpilot is authenticated
+    async fn authenticated(wp: &RwLock<Wattpilot>) -> Result<()> {
+        timeout(Duration::from_secs(5), async {
+            while !wp.read().await.authenticated {
+                sleep(Duration::from_millis(10)).await;
+            }
+        }).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn new_serial_recomputes_the_hash() -> Result<()> {
+        let url = mock_wattpilot(|mut socket, _| async move {
+            let _ = handshake(&mut socket, "87654321", json!({"type": "authSuccess"})).await;
+        }).await?;
+        let dir = std::env::temp_dir().join(format!("wattpilot-hash-serial-{}", std::process::id()));
+        fs::create_dir_all(&dir).await?;
+        let path = dir.join("hashes.json");
+        let mut settings = Handshake {
+            hash_cache: Some(path.clone()),
+            ..handshake_settings(Some((SERIAL.to_owned(), "h".repeat(32))), Duration::from_secs(5))
+        };
+        assert!(authenticate(url, &mut settings).await?);
+        let hashed_pw = hash_password("password", "87654321");
+        assert_eq!(settings.hashed_pw, Some(("87654321".to_owned(), hashed_pw.clone())));
+        // the accepted hash is cached for the next start
+        assert_eq!(load_cached_hash(&path, "87654321").await, Some(hashed_pw));
+        fs::remove_dir_all(&dir).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handshake_times_out_without_response() -> Result<()> {
+        let url = mock_wattpilot(|mut socket, _| async move {
+            // accept the websocket, but never send anything
+            let _ = socket.next().await;
+        }).await?;
+        let mut settings = handshake_settings(None, Duration::from_millis(200));
+        let started = Instant::now();
+        let result = timeout(Duration::from_secs(5), authenticate(url, &mut settings)).await?;
+        assert!(result.is_err_and(|err| err.to_string().contains("Timeout waiting for 'hello'")));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejected_cached_hash_is_evicted() -> Result<()> {
+        let url = mock_wattpilot(|mut socket, _| async move {
+            let _ = handshake(&mut socket, SERIAL, json!({"type": "authError", "message": "Wrong password"})).await;
+            let _ = socket.next().await;
+        }).await?;
+        let dir = std::env::temp_dir().join(format!("wattpilot-hash-evict-{}", std::process::id()));
+        fs::create_dir_all(&dir).await?;
+        let path = dir.join("hashes.json");
+        store_cached_hash(&path, SERIAL, Some(&"h".repeat(32))).await?;
+        store_cached_hash(&path, "other", Some("hash")).await?;
+        let mut settings = Handshake { hash_cache: Some(path.clone()), ..handshake_settings(None, Duration::from_secs(5)) };
+        let result = authenticate(url, &mut settings).await;
+        assert!(result.is_err_and(|err| err.to_string().contains("rejected")));
+        assert!(settings.hashed_pw.is_none());
+        assert_eq!(load_cached_hash(&path, SERIAL).await, None);
+        assert_eq!(load_cached_hash(&path, "other").await.as_deref(), Some("hash"));
+        fs::remove_dir_all(&dir).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unexpected_auth_response_is_no_success() -> Result<()> {
+        let url = mock_wattpilot(|mut socket, _| async move {
+            let _ = handshake(&mut socket, SERIAL, json!({"type": "hello", "serial": SERIAL})).await;
+            let _ = socket.next().await;
+        }).await?;
+        let mut settings = handshake_settings(Some((SERIAL.to_owned(), "h".repeat(32))), Duration::from_secs(5));
+        let result = authenticate(url.clone(), &mut settings).await;
+        assert!(result.is_err_and(|err| err.to_string().contains("Unexpected response")));
+
+        // the handler stays unauthenticated
+        let wp = Arc::new(RwLock::new(wattpilot(url)));
+        let handler = tokio::spawn(Wattpilot::start_handler(Arc::clone(&wp)));
+        timeout(Duration::from_secs(5), async {
+            while wp.read().await.last_error.is_none() {
+                sleep(Duration::from_millis(10)).await;
+            }
+        }).await?;
+        let read = wp.read().await;
+        assert!(!read.authenticated);
+        assert!(read.last_error.as_ref().is_some_and(|err| err.starts_with("Authentication failed")));
+        read.shutdown.cancel();
+        drop(read);
+        timeout(Duration::from_secs(5), handler).await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handshake_does_not_hold_the_lock() -> Result<()> {
+        let (hello, mut wait) = mpsc::unbounded_channel::<()>();
+        let url = mock_wattpilot(move |mut socket, _| {
+            let hello = hello.clone();
+            async move {
+                // the first message is only sent once the test has checked the lock
+                hello.closed().await;
+                let _ = handshake(&mut socket, SERIAL, json!({"type": "authSuccess"})).await;
+                let _ = socket.next().await;
+            }
+        }).await?;
+        let wp = Arc::new(RwLock::new(wattpilot(url)));
+        let handler = tokio::spawn(Wattpilot::start_handler(Arc::clone(&wp)));
+        sleep(Duration::from_millis(200)).await;
+        assert!(!wp.read().await.authenticated);
+        timeout(Duration::from_millis(100), wp.write()).await?.last_error = None;
+
+        wait.close();
+        authenticated(&wp).await?;
+        wp.read().await.shutdown.cancel();
+        timeout(Duration::from_secs(5), handler).await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn status_follows_socket_authentication_and_data() -> Result<()> {
+        let config = Config::default();
+        assert_eq!(Wattpilot::status(None, &config).await, WattpilotStatus::Disconnected);
+
+        // steps of the mock, released by the test one after another
+        let (step, steps) = mpsc::unbounded_channel::<()>();
+        let steps = Arc::new(tokio::sync::Mutex::new(steps));
+        let url = mock_wattpilot(move |mut socket, _| {
+            let steps = Arc::clone(&steps);
+            async move {
+                let mut steps = steps.lock().await;
+                steps.recv().await;
+                let _ = handshake(&mut socket, SERIAL, json!({"type": "authSuccess"})).await;
+                steps.recv().await;
+                let delta = json!({"type": "deltaStatus", "status": {"car": 2}}).to_string();
+                let _ = socket.send(Message::from(delta)).await;
+                let _ = socket.next().await;
+            }
+        }).await?;
+        let wp = Arc::new(RwLock::new(wattpilot(url)));
+        assert_eq!(Wattpilot::status(Some(&wp), &config).await, WattpilotStatus::Disconnected);
+        let handler = tokio::spawn(Wattpilot::start_handler(Arc::clone(&wp)));
+
+        let status = |expected| {
+            let wp = Arc::clone(&wp);
+            let config = config.clone();
+            timeout(Duration::from_secs(5), async move {
+                while Wattpilot::status(Some(&wp), &config).await != expected {
+                    sleep(Duration::from_millis(10)).await;
+                }
+            })
+        };
+        status(WattpilotStatus::Connected).await?;
+        step.send(())?;
+        // authenticated, but nothing received yet
+        status(WattpilotStatus::Stale).await?;
+        step.send(())?;
+        status(WattpilotStatus::Authenticated).await?;
+
+        wp.read().await.shutdown.cancel();
+        timeout(Duration::from_secs(5), handler).await??;
+        assert_eq!(Wattpilot::status(Some(&wp), &config).await, WattpilotStatus::Disconnected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn newer_handler_closes_the_older_connection() -> Result<()> {
+        // number of every closed connection, and whether the client sent a close frame
+        let (report, mut closed) = mpsc::unbounded_channel();
+        let url = mock_wattpilot(move |mut socket, connection| {
+            let report = report.clone();
+            async move {
+                if handshake(&mut socket, SERIAL, json!({"type": "authSuccess"})).await.is_err() {
+                    return;
+                }
+                let close_frame = loop {
+                    select! {
+                        () = sleep(Duration::from_millis(20)) => {
+                            let delta = json!({"type": "deltaStatus", "status": {"car": 2}}).to_string();
+                            if socket.send(Message::from(delta)).await.is_err() {
+                                break false;
+                            }
+                        }
+                        message = socket.next() => match message {
+                            Some(Ok(Message::Close(_))) => break true,
+                            Some(Ok(_)) => {}
+                            Some(Err(_)) | None => break false,
+                        },
+                    }
+                };
+                let _ = report.send((connection, close_frame));
+            }
+        }).await?;
+        let wp = Arc::new(RwLock::new(wattpilot(url)));
+        let shutdown = wp.read().await.shutdown.clone();
+
+        let first = tokio::spawn(Wattpilot::start_handler(Arc::clone(&wp)));
+        authenticated(&wp).await?;
+        let second = tokio::spawn(Wattpilot::start_handler(Arc::clone(&wp)));
+        timeout(Duration::from_secs(5), first).await??;
+        assert_eq!(timeout(Duration::from_secs(5), closed.recv()).await?, Some((0, true)));
+
+        // the newer handler keeps its connection
+        authenticated(&wp).await?;
+        sleep(Duration::from_millis(200)).await;
+        assert!(!second.is_finished());
+        assert!(closed.try_recv().is_err());
+        assert_eq!(wp.read().await.data.read().await.car_state, CarState::Charging);
+
+        shutdown.cancel();
+        timeout(Duration::from_secs(5), second).await??;
+        assert_eq!(timeout(Duration::from_secs(5), closed.recv()).await?, Some((1, true)));
+        Ok(())
+    }
+}